@@ -0,0 +1,12 @@
+create table if not exists keyword_responses (
+    id integer primary key autoincrement not null,
+    guild_id integer not null,
+    -- json arrays
+    keywords text not null,
+    responses text not null,
+    weights text
+);
+
+create index if not exists keyword_responses_guild on keyword_responses (guild_id);
+
+alter table server_config add keywords_seeded boolean not null default false;
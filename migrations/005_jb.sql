@@ -0,0 +1,8 @@
+-- seconds before a keyword can respond again in the same channel
+alter table keyword_responses add cooldown integer;
+
+-- max keyword/dad bot responses per channel and per user within kw_limit_window seconds
+alter table server_config add kw_channel_limit integer;
+alter table server_config add kw_user_limit integer;
+alter table server_config add kw_limit_window integer;
+alter table server_config add dad_bot_cooldown integer;
@@ -0,0 +1,9 @@
+-- V-Bucks and XP are tracked per guild. The vbucks and exp columns of users are no longer updated,
+-- they are only copied into a member's row the first time they show up in a guild.
+create table if not exists members (
+    guild_id integer not null,
+    user_id integer not null,
+    vbucks integer not null default 0,
+    exp integer not null default 0,
+    PRIMARY KEY (guild_id, user_id)
+);
@@ -0,0 +1,3 @@
+-- backs the leaderboard queries
+create index if not exists members_guild_exp on members (guild_id, exp desc);
+create index if not exists members_guild_vbucks on members (guild_id, vbucks desc);
@@ -0,0 +1,7 @@
+-- unix time of the last message that earned a reward, and a hash of its content
+alter table members add last_rewarded_at integer;
+alter table members add last_message_hash integer;
+
+-- seconds between rewarded messages and the shortest message that can be rewarded
+alter table server_config add xp_cooldown integer;
+alter table server_config add xp_min_length integer;
@@ -0,0 +1,9 @@
+create table if not exists level_roles (
+    guild_id integer not null,
+    role_id integer not null,
+    level integer not null,
+    PRIMARY KEY (guild_id, role_id)
+);
+
+-- take a member's older level roles away when they earn a new one
+alter table server_config add replace_level_roles boolean not null default false;
@@ -0,0 +1,5 @@
+-- where level up messages go, see db::LevelUpMode
+alter table server_config add level_up_mode integer not null default 0;
+alter table server_config add level_up_channel_id integer;
+-- template with {user}, {old_level}, {new_level} and {vbucks} placeholders
+alter table server_config add level_up_message text;
@@ -0,0 +1,8 @@
+-- json levels::XpCurve, null is the original logarithmic curve
+alter table server_config add xp_curve text;
+
+-- inclusive ranges of V-Bucks and XP given out for each rewarded message
+alter table server_config add money_min integer;
+alter table server_config add money_max integer;
+alter table server_config add exp_min integer;
+alter table server_config add exp_max integer;
@@ -0,0 +1,13 @@
+-- every V-Buck transfer between two members, see db::TransferReason for reasons
+create table if not exists transactions (
+    id integer primary key autoincrement,
+    guild_id integer not null,
+    from_user integer not null,
+    to_user integer not null,
+    amount integer not null,
+    reason text not null,
+    created_at integer not null default (unixepoch())
+);
+
+create index if not exists transactions_from on transactions(guild_id, from_user, created_at);
+create index if not exists transactions_to on transactions(guild_id, to_user, created_at);
@@ -0,0 +1,2 @@
+-- optional message left by whoever sent a transfer
+alter table transactions add note text;
@@ -0,0 +1,10 @@
+-- last time each member claimed a periodic reward and how many periods in a row they have claimed,
+-- kind is a claims::ClaimKind
+create table if not exists claims (
+    guild_id integer not null,
+    user_id integer not null,
+    kind integer not null,
+    last_claimed_at integer not null,
+    streak integer not null,
+    PRIMARY KEY (guild_id, user_id, kind)
+);
@@ -0,0 +1,19 @@
+-- every change to a member's V-Bucks, see db::LedgerReason for reasons. counterparty is the other
+-- member of a transfer.
+create table if not exists ledger (
+    id integer primary key autoincrement,
+    guild_id integer not null,
+    user_id integer not null,
+    amount integer not null,
+    reason text not null,
+    counterparty integer,
+    created_at integer not null default (unixepoch())
+);
+
+create index if not exists ledger_member on ledger(guild_id, user_id, id);
+
+-- transfers made before the ledger existed
+insert into ledger(guild_id, user_id, amount, reason, counterparty, created_at)
+select guild_id, from_user, -amount, reason, to_user, created_at from transactions order by id;
+insert into ledger(guild_id, user_id, amount, reason, counterparty, created_at)
+select guild_id, to_user, amount, reason, from_user, created_at from transactions order by id;
@@ -0,0 +1,7 @@
+-- role that can use /economy, along with anyone that can manage the server
+alter table server_config add economy_admin_role_id integer;
+-- where admin actions get announced, error_channel_id is used if this isn't set
+alter table server_config add audit_channel_id integer;
+
+-- what a ledger row changed, 'vbucks' or 'exp'
+alter table ledger add currency text not null default 'vbucks';
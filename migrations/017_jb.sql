@@ -0,0 +1,15 @@
+-- V-Bucks Johnson pays out of when members play against him, his winnings go back into it
+alter table server_config add house_bankroll integer not null default 0;
+-- percent Johnson keeps from what he pays out, 5 if this isn't set
+alter table server_config add house_edge integer;
+-- whether Johnson counters members' favourite moves instead of picking at random
+alter table server_config add rps_adaptive boolean not null default false;
+
+-- how often each member has thrown each move against Johnson
+create table if not exists rps_moves (
+    guild_id integer not null,
+    user_id integer not null,
+    move text not null,
+    count integer not null default 0,
+    primary key (guild_id, user_id, move)
+);
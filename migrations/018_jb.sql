@@ -0,0 +1,15 @@
+-- V-Bucks taken out of members' balances while a game is being played. Everything still in here
+-- at startup belonged to a game that never finished and gets refunded.
+create table if not exists escrows (
+    id integer primary key autoincrement,
+    guild_id integer not null,
+    game text not null,
+    created_at integer not null default (unixepoch())
+);
+
+create table if not exists escrow_holds (
+    escrow_id integer not null,
+    user_id integer not null,
+    amount integer not null,
+    primary key (escrow_id, user_id)
+);
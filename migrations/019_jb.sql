@@ -0,0 +1,3 @@
+-- global balances are only carried into the first guild a user shows up in. users who are already
+-- in a guild have had theirs carried over, so clear them to stop new guilds getting another copy
+update users set vbucks = 0, exp = 0 where id in (select user_id from members);
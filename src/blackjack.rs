@@ -0,0 +1,772 @@
+use std::fmt::Display;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+pub const DECKS_IN_SHOE: usize = 6;
+// Splitting can't go past this many hands
+const MAX_HANDS: usize = 4;
+// The dealer draws until reaching this, standing on soft totals too
+const DEALER_STANDS_ON: u32 = 17;
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Suit {
+    Clubs,
+    Diamonds,
+    Hearts,
+    Spades,
+}
+
+impl Suit {
+    const ALL: [Suit; 4] = [Suit::Clubs, Suit::Diamonds, Suit::Hearts, Suit::Spades];
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Suit::Clubs => "♣",
+            Suit::Diamonds => "♦",
+            Suit::Hearts => "♥",
+            Suit::Spades => "♠",
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Rank {
+    Ace,
+    Two,
+    Three,
+    Four,
+    Five,
+    Six,
+    Seven,
+    Eight,
+    Nine,
+    Ten,
+    Jack,
+    Queen,
+    King,
+}
+
+impl Rank {
+    const ALL: [Rank; 13] = [
+        Rank::Ace,
+        Rank::Two,
+        Rank::Three,
+        Rank::Four,
+        Rank::Five,
+        Rank::Six,
+        Rank::Seven,
+        Rank::Eight,
+        Rank::Nine,
+        Rank::Ten,
+        Rank::Jack,
+        Rank::Queen,
+        Rank::King,
+    ];
+
+    /// What the card counts for, aces count as 1 here and get bumped up by Hand::value
+    pub fn value(&self) -> u32 {
+        match self {
+            Rank::Ace => 1,
+            Rank::Two => 2,
+            Rank::Three => 3,
+            Rank::Four => 4,
+            Rank::Five => 5,
+            Rank::Six => 6,
+            Rank::Seven => 7,
+            Rank::Eight => 8,
+            Rank::Nine => 9,
+            Rank::Ten | Rank::Jack | Rank::Queen | Rank::King => 10,
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Rank::Ace => "A",
+            Rank::Two => "2",
+            Rank::Three => "3",
+            Rank::Four => "4",
+            Rank::Five => "5",
+            Rank::Six => "6",
+            Rank::Seven => "7",
+            Rank::Eight => "8",
+            Rank::Nine => "9",
+            Rank::Ten => "10",
+            Rank::Jack => "J",
+            Rank::Queen => "Q",
+            Rank::King => "K",
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct Card {
+    pub rank: Rank,
+    pub suit: Suit,
+}
+
+impl Display for Card {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.rank.symbol(), self.suit.symbol())
+    }
+}
+
+/// Several decks shuffled together. The same seed always deals the same cards, so a game can be
+/// replayed from its seed.
+#[derive(Debug)]
+pub struct Shoe {
+    cards: Vec<Card>,
+    decks: usize,
+    rng: StdRng,
+}
+
+impl Shoe {
+    pub fn new(decks: usize, seed: u64) -> Shoe {
+        let mut shoe = Shoe {
+            cards: Vec::with_capacity(decks * 52),
+            decks,
+            rng: StdRng::seed_from_u64(seed),
+        };
+        shoe.refill();
+
+        shoe
+    }
+
+    /// Deals the next card, shuffling a fresh shoe if this one ran out
+    pub fn draw(&mut self) -> Card {
+        if self.cards.is_empty() {
+            self.refill();
+        }
+
+        self.cards.pop().expect("a refilled shoe is never empty")
+    }
+
+    fn refill(&mut self) {
+        for _ in 0..self.decks {
+            for suit in Suit::ALL {
+                for rank in Rank::ALL {
+                    self.cards.push(Card { rank, suit });
+                }
+            }
+        }
+
+        self.cards.shuffle(&mut self.rng);
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct Hand {
+    pub cards: Vec<Card>,
+}
+
+impl Hand {
+    /// The best total for the hand, counting one ace as 11 if that doesn't bust it
+    pub fn value(&self) -> u32 {
+        let hard = self.hard_value();
+        if self.has_ace() && hard + 10 <= 21 {
+            hard + 10
+        } else {
+            hard
+        }
+    }
+
+    /// Whether an ace is being counted as 11
+    pub fn is_soft(&self) -> bool {
+        self.has_ace() && self.hard_value() + 10 <= 21
+    }
+
+    pub fn is_bust(&self) -> bool {
+        self.value() > 21
+    }
+
+    /// 21 from the first two cards
+    pub fn is_natural(&self) -> bool {
+        self.cards.len() == 2 && self.value() == 21
+    }
+
+    fn hard_value(&self) -> u32 {
+        self.cards.iter().map(|c| c.rank.value()).sum()
+    }
+
+    fn has_ace(&self) -> bool {
+        self.cards.iter().any(|c| c.rank == Rank::Ace)
+    }
+}
+
+impl Display for Hand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cards = self
+            .cards
+            .iter()
+            .map(Card::to_string)
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        if self.is_soft() {
+            write!(f, "{cards} (soft {})", self.value())
+        } else {
+            write!(f, "{cards} ({})", self.value())
+        }
+    }
+}
+
+/// One of the player's hands along with what's riding on it
+#[derive(Debug)]
+pub struct PlayerHand {
+    pub hand: Hand,
+    pub bet: u32,
+    pub doubled: bool,
+    /// Hands made by splitting can't be a natural blackjack
+    pub split: bool,
+    /// Whether the player is done playing it
+    pub done: bool,
+}
+
+impl PlayerHand {
+    fn new(hand: Hand, bet: u32, split: bool) -> PlayerHand {
+        PlayerHand {
+            hand,
+            bet,
+            doubled: false,
+            split,
+            done: false,
+        }
+    }
+
+    pub fn is_blackjack(&self) -> bool {
+        !self.split && self.hand.is_natural()
+    }
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Action {
+    Hit,
+    Stand,
+    Double,
+    Split,
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum ActionError {
+    /// Every hand has already been played
+    GameOver,
+    /// The action can't be taken on the current hand
+    NotAllowed(Action),
+}
+
+impl Display for ActionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ActionError::GameOver => write!(f, "the game is already over"),
+            ActionError::NotAllowed(action) => write!(f, "can't {action:?} this hand"),
+        }
+    }
+}
+
+impl std::error::Error for ActionError {}
+
+/// How a hand did against the dealer
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum HandOutcome {
+    /// A natural that the dealer didn't match, pays 3:2
+    Blackjack,
+    Win,
+    Push,
+    Loss,
+}
+
+impl HandOutcome {
+    /// What the player gets back for a hand, their bet included. Blackjack payouts round down.
+    pub fn returned(&self, bet: u32) -> i64 {
+        let bet = i64::from(bet);
+        match self {
+            HandOutcome::Blackjack => bet + bet * 3 / 2,
+            HandOutcome::Win => bet * 2,
+            HandOutcome::Push => bet,
+            HandOutcome::Loss => 0,
+        }
+    }
+
+    /// What the player made or lost on a hand
+    pub fn net(&self, bet: u32) -> i64 {
+        self.returned(bet) - i64::from(bet)
+    }
+}
+
+/// A round of blackjack between one player and the dealer. The dealer peeks for blackjack, stands
+/// on all 17s, and the player can double any two card hand and split pairs up to MAX_HANDS hands.
+/// Split aces only get one more card each.
+#[derive(Debug)]
+pub struct Game {
+    shoe: Shoe,
+    pub hands: Vec<PlayerHand>,
+    pub dealer: Hand,
+    active: usize,
+}
+
+impl Game {
+    pub fn deal(bet: u32, mut shoe: Shoe) -> Game {
+        let mut player = Hand::default();
+        let mut dealer = Hand::default();
+
+        player.cards.push(shoe.draw());
+        dealer.cards.push(shoe.draw());
+        player.cards.push(shoe.draw());
+        dealer.cards.push(shoe.draw());
+
+        let mut game = Game {
+            shoe,
+            hands: vec![PlayerHand::new(player, bet, false)],
+            dealer,
+            active: 0,
+        };
+
+        // Nothing left to play if either side has a natural
+        if game.hands[0].hand.is_natural() || game.dealer.is_natural() {
+            game.hands[0].done = true;
+            game.advance();
+        }
+
+        game
+    }
+
+    /// The index of the hand being played, or None once every hand is done
+    pub fn active_hand(&self) -> Option<usize> {
+        self.hands.get(self.active).map(|_| self.active)
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.active_hand().is_none()
+    }
+
+    pub fn can(&self, action: Action) -> bool {
+        let Some(hand) = self.hands.get(self.active) else {
+            return false;
+        };
+
+        match action {
+            Action::Hit | Action::Stand => true,
+            Action::Double => hand.hand.cards.len() == 2,
+            Action::Split => {
+                let cards = &hand.hand.cards;
+                self.hands.len() < MAX_HANDS
+                    && cards.len() == 2
+                    && cards[0].rank.value() == cards[1].rank.value()
+            }
+        }
+    }
+
+    /// How much more the player has to put in to take an action
+    pub fn extra_stake(&self, action: Action) -> u32 {
+        match (action, self.hands.get(self.active)) {
+            (Action::Double | Action::Split, Some(hand)) => hand.bet,
+            _ => 0,
+        }
+    }
+
+    pub fn apply(&mut self, action: Action) -> Result<(), ActionError> {
+        if self.is_over() {
+            return Err(ActionError::GameOver);
+        }
+
+        if !self.can(action) {
+            return Err(ActionError::NotAllowed(action));
+        }
+
+        let active = self.active;
+        match action {
+            Action::Hit => {
+                let card = self.shoe.draw();
+                let hand = &mut self.hands[active];
+                hand.hand.cards.push(card);
+                hand.done = hand.hand.value() >= 21;
+            }
+            Action::Stand => self.hands[active].done = true,
+            Action::Double => {
+                let card = self.shoe.draw();
+                let hand = &mut self.hands[active];
+                hand.bet *= 2;
+                hand.doubled = true;
+                hand.hand.cards.push(card);
+                hand.done = true;
+            }
+            Action::Split => {
+                let second = self.hands[active]
+                    .hand
+                    .cards
+                    .pop()
+                    .expect("only pairs can be split");
+                let bet = self.hands[active].bet;
+                let aces = second.rank == Rank::Ace;
+
+                let first_card = self.shoe.draw();
+                let second_card = self.shoe.draw();
+
+                let first = &mut self.hands[active];
+                first.split = true;
+                first.hand.cards.push(first_card);
+                first.done = aces || first.hand.value() == 21;
+
+                let mut new_hand = PlayerHand::new(
+                    Hand {
+                        cards: vec![second, second_card],
+                    },
+                    bet,
+                    true,
+                );
+                new_hand.done = aces || new_hand.hand.value() == 21;
+                self.hands.insert(active + 1, new_hand);
+            }
+        }
+
+        self.advance();
+
+        Ok(())
+    }
+
+    /// Plays out the dealer's hand and scores every one of the player's hands against it
+    pub fn finish(&mut self) -> Vec<HandOutcome> {
+        while let Some(hand) = self.hands.get_mut(self.active) {
+            hand.done = true;
+            self.advance();
+        }
+
+        // The dealer only needs to draw if there's a hand left to beat
+        let live = self
+            .hands
+            .iter()
+            .any(|h| !h.hand.is_bust() && !h.is_blackjack());
+        if live && !self.dealer.is_natural() {
+            while self.dealer.value() < DEALER_STANDS_ON {
+                self.dealer.cards.push(self.shoe.draw());
+            }
+        }
+
+        self.hands
+            .iter()
+            .map(|hand| score(hand, &self.dealer))
+            .collect()
+    }
+
+    /// Everything the player has bet across their hands
+    pub fn staked(&self) -> i64 {
+        self.hands.iter().map(|hand| i64::from(hand.bet)).sum()
+    }
+
+    /// Everything the player gets back, bets included
+    pub fn total_returned(&self, outcomes: &[HandOutcome]) -> i64 {
+        self.hands
+            .iter()
+            .zip(outcomes)
+            .map(|(hand, outcome)| outcome.returned(hand.bet))
+            .sum()
+    }
+
+    fn advance(&mut self) {
+        while self.hands.get(self.active).is_some_and(|h| h.done) {
+            self.active += 1;
+        }
+    }
+}
+
+fn score(hand: &PlayerHand, dealer: &Hand) -> HandOutcome {
+    let player = hand.hand.value();
+    let dealer_value = dealer.value();
+
+    if hand.hand.is_bust() {
+        HandOutcome::Loss
+    } else if hand.is_blackjack() && dealer.is_natural() {
+        HandOutcome::Push
+    } else if hand.is_blackjack() {
+        HandOutcome::Blackjack
+    } else if dealer.is_natural() || (!dealer.is_bust() && dealer_value > player) {
+        HandOutcome::Loss
+    } else if dealer.is_bust() || player > dealer_value {
+        HandOutcome::Win
+    } else {
+        HandOutcome::Push
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card(rank: Rank) -> Card {
+        Card {
+            rank,
+            suit: Suit::Spades,
+        }
+    }
+
+    fn hand(ranks: &[Rank]) -> Hand {
+        Hand {
+            cards: ranks.iter().map(|r| card(*r)).collect(),
+        }
+    }
+
+    /// A shoe that deals exactly these cards in this order
+    fn stacked(ranks: &[Rank]) -> Shoe {
+        let mut shoe = Shoe::new(1, 0);
+        shoe.cards = ranks.iter().rev().map(|r| card(*r)).collect();
+        shoe
+    }
+
+    /// Deals a game where the cards go player, dealer, player, dealer, then anything after
+    fn game(bet: u32, ranks: &[Rank]) -> Game {
+        Game::deal(bet, stacked(ranks))
+    }
+
+    #[test]
+    fn aces_count_as_eleven_when_they_fit() {
+        assert_eq!(hand(&[Rank::Ace, Rank::Six]).value(), 17);
+        assert!(hand(&[Rank::Ace, Rank::Six]).is_soft());
+        assert_eq!(hand(&[Rank::Ace, Rank::Six, Rank::Ten]).value(), 17);
+        assert!(!hand(&[Rank::Ace, Rank::Six, Rank::Ten]).is_soft());
+        assert_eq!(hand(&[Rank::Ace, Rank::Ace, Rank::Nine]).value(), 21);
+        assert!(hand(&[Rank::King, Rank::Queen, Rank::Two]).is_bust());
+    }
+
+    #[test]
+    fn naturals_only_come_from_two_cards() {
+        assert!(hand(&[Rank::Ace, Rank::King]).is_natural());
+        assert!(!hand(&[Rank::Seven, Rank::Seven, Rank::Seven]).is_natural());
+    }
+
+    #[test]
+    fn shoe_deals_every_card_of_every_deck() {
+        let mut shoe = Shoe::new(DECKS_IN_SHOE, 42);
+        let dealt: Vec<Card> = (0..DECKS_IN_SHOE * 52).map(|_| shoe.draw()).collect();
+
+        for suit in Suit::ALL {
+            for rank in Rank::ALL {
+                let card = Card { rank, suit };
+                assert_eq!(dealt.iter().filter(|&&c| c == card).count(), DECKS_IN_SHOE);
+            }
+        }
+
+        // Running out reshuffles instead of running dry
+        shoe.draw();
+        assert_eq!(shoe.cards.len(), DECKS_IN_SHOE * 52 - 1);
+    }
+
+    #[test]
+    fn payouts() {
+        assert_eq!(HandOutcome::Blackjack.returned(10), 25);
+        assert_eq!(HandOutcome::Blackjack.net(10), 15);
+        // 3:2 on an odd bet rounds down
+        assert_eq!(HandOutcome::Blackjack.net(5), 7);
+        assert_eq!(HandOutcome::Win.net(10), 10);
+        assert_eq!(HandOutcome::Push.net(10), 0);
+        assert_eq!(HandOutcome::Loss.net(10), -10);
+    }
+
+    #[test]
+    fn natural_pays_three_to_two() {
+        let mut game = game(10, &[Rank::Ace, Rank::Nine, Rank::King, Rank::Eight]);
+        assert!(game.is_over());
+
+        let outcomes = game.finish();
+        assert_eq!(outcomes, vec![HandOutcome::Blackjack]);
+        assert_eq!(game.total_returned(&outcomes), 25);
+        // The dealer doesn't draw against a blackjack
+        assert_eq!(game.dealer.cards.len(), 2);
+    }
+
+    #[test]
+    fn dealer_natural_ends_the_round() {
+        let mut game = game(10, &[Rank::Ten, Rank::Ace, Rank::Nine, Rank::Queen]);
+        assert!(game.is_over());
+        assert_eq!(game.apply(Action::Hit), Err(ActionError::GameOver));
+        assert_eq!(game.finish(), vec![HandOutcome::Loss]);
+    }
+
+    #[test]
+    fn both_naturals_push() {
+        let mut game = game(10, &[Rank::Ace, Rank::Ace, Rank::King, Rank::Queen]);
+        let outcomes = game.finish();
+        assert_eq!(outcomes, vec![HandOutcome::Push]);
+        assert_eq!(game.total_returned(&outcomes), 10);
+    }
+
+    #[test]
+    fn busting_loses_even_if_the_dealer_busts() {
+        let mut game = game(
+            10,
+            &[
+                Rank::Ten,
+                Rank::Ten,
+                Rank::Six,
+                Rank::Six,
+                Rank::King,
+                Rank::Nine,
+            ],
+        );
+        game.apply(Action::Hit).unwrap();
+        assert!(game.is_over());
+
+        assert_eq!(game.finish(), vec![HandOutcome::Loss]);
+        // Nothing left to beat, so the dealer keeps their 16
+        assert_eq!(game.dealer.value(), 16);
+    }
+
+    #[test]
+    fn dealer_stands_on_soft_seventeen() {
+        let mut game = game(10, &[Rank::Ten, Rank::Ace, Rank::Eight, Rank::Six]);
+        game.apply(Action::Stand).unwrap();
+
+        assert_eq!(game.finish(), vec![HandOutcome::Win]);
+        assert_eq!(game.dealer.cards.len(), 2);
+        assert_eq!(game.dealer.value(), 17);
+    }
+
+    #[test]
+    fn dealer_draws_to_seventeen() {
+        let mut game = game(
+            10,
+            &[
+                Rank::Ten,
+                Rank::Ten,
+                Rank::Nine,
+                Rank::Two,
+                Rank::Three,
+                Rank::Four,
+            ],
+        );
+        game.apply(Action::Stand).unwrap();
+
+        // 12 + 3 + 4 is 19, same as the player
+        assert_eq!(game.finish(), vec![HandOutcome::Push]);
+        assert_eq!(game.dealer.value(), 19);
+    }
+
+    #[test]
+    fn doubling_doubles_the_bet_and_takes_one_card() {
+        let mut game = game(
+            10,
+            &[
+                Rank::Six,
+                Rank::Ten,
+                Rank::Five,
+                Rank::Seven,
+                Rank::Ten,
+                Rank::Ten,
+            ],
+        );
+        assert_eq!(game.extra_stake(Action::Double), 10);
+        game.apply(Action::Double).unwrap();
+
+        assert!(game.is_over());
+        assert_eq!(game.hands[0].bet, 20);
+        assert_eq!(game.hands[0].hand.value(), 21);
+
+        let outcomes = game.finish();
+        assert_eq!(outcomes, vec![HandOutcome::Win]);
+        assert_eq!(game.total_returned(&outcomes), 40);
+    }
+
+    #[test]
+    fn doubling_needs_two_cards() {
+        let mut game = game(
+            10,
+            &[Rank::Two, Rank::Ten, Rank::Three, Rank::Seven, Rank::Four],
+        );
+        game.apply(Action::Hit).unwrap();
+
+        assert!(!game.can(Action::Double));
+        assert_eq!(
+            game.apply(Action::Double),
+            Err(ActionError::NotAllowed(Action::Double))
+        );
+    }
+
+    #[test]
+    fn splitting_plays_each_hand_separately() {
+        let mut game = game(
+            10,
+            &[
+                Rank::Eight,
+                Rank::Ten,
+                Rank::Eight,
+                Rank::Seven,
+                // First hand gets a 3, second gets a king
+                Rank::Three,
+                Rank::King,
+                // First hand doubles into a 10
+                Rank::Ten,
+            ],
+        );
+        assert!(game.can(Action::Split));
+        assert_eq!(game.extra_stake(Action::Split), 10);
+        game.apply(Action::Split).unwrap();
+
+        assert_eq!(game.hands.len(), 2);
+        assert_eq!(game.active_hand(), Some(0));
+
+        game.apply(Action::Double).unwrap();
+        assert_eq!(game.active_hand(), Some(1));
+        game.apply(Action::Stand).unwrap();
+
+        let outcomes = game.finish();
+        // 21 and 18 against the dealer's 17
+        assert_eq!(outcomes, vec![HandOutcome::Win, HandOutcome::Win]);
+        assert_eq!(game.total_returned(&outcomes), 40 + 20);
+    }
+
+    #[test]
+    fn split_aces_get_one_card_and_no_blackjack() {
+        let mut game = game(
+            10,
+            &[
+                Rank::Ace,
+                Rank::Ten,
+                Rank::Ace,
+                Rank::Seven,
+                Rank::King,
+                Rank::Five,
+            ],
+        );
+        game.apply(Action::Split).unwrap();
+        assert!(game.is_over());
+
+        // 21 after a split is a regular win, and 16 loses to 17
+        let outcomes = game.finish();
+        assert_eq!(outcomes, vec![HandOutcome::Win, HandOutcome::Loss]);
+        assert_eq!(game.total_returned(&outcomes), 20);
+    }
+
+    #[test]
+    fn splitting_is_limited() {
+        let mut game = game(
+            10,
+            &[
+                Rank::Nine,
+                Rank::Ten,
+                Rank::Nine,
+                Rank::Seven,
+                Rank::Nine,
+                Rank::Two,
+                Rank::Nine,
+                Rank::Two,
+                Rank::Nine,
+                Rank::Two,
+            ],
+        );
+        game.apply(Action::Split).unwrap();
+        game.apply(Action::Split).unwrap();
+        game.apply(Action::Split).unwrap();
+
+        assert_eq!(game.hands.len(), MAX_HANDS);
+        assert!(!game.can(Action::Split));
+    }
+
+    #[test]
+    fn finishing_early_stands_every_hand() {
+        let mut game = game(10, &[Rank::Ten, Rank::Ten, Rank::Nine, Rank::Eight]);
+        assert!(!game.is_over());
+
+        assert_eq!(game.finish(), vec![HandOutcome::Win]);
+        assert!(game.is_over());
+    }
+}
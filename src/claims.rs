@@ -0,0 +1,181 @@
+const DAY: i64 = 60 * 60 * 24;
+const WEEK: i64 = DAY * 7;
+// The unix epoch was a Thursday, shifting by 3 days makes weeks start on Monday
+const WEEK_OFFSET: i64 = DAY * 3;
+
+// Each claim in a row adds this much to the multiplier, up to MAX_STREAK_BONUS
+const STREAK_BONUS: f64 = 0.1;
+const MAX_STREAK_BONUS: f64 = 1.0;
+
+/// A reward that can be claimed once per UTC day or week
+#[repr(u8)]
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum ClaimKind {
+    Daily = 0,
+    Weekly,
+}
+
+impl ClaimKind {
+    pub fn base_reward(&self) -> i64 {
+        match self {
+            ClaimKind::Daily => 100,
+            ClaimKind::Weekly => 1000,
+        }
+    }
+
+    /// Which day or week a unix timestamp falls in. Periods always start at midnight UTC, so
+    /// nobody's timezone changes when they can claim.
+    pub fn period(&self, unix: i64) -> i64 {
+        match self {
+            ClaimKind::Daily => unix.div_euclid(DAY),
+            ClaimKind::Weekly => (unix + WEEK_OFFSET).div_euclid(WEEK),
+        }
+    }
+
+    /// When a day or week starts, as a unix timestamp
+    pub fn period_start(&self, period: i64) -> i64 {
+        match self {
+            ClaimKind::Daily => period * DAY,
+            ClaimKind::Weekly => period * WEEK - WEEK_OFFSET,
+        }
+    }
+
+    /// When someone who claimed at this time can claim again
+    pub fn next_claim_at(&self, claimed_at: i64) -> i64 {
+        self.period_start(self.period(claimed_at) + 1)
+    }
+
+    /// The streak someone will be on if they claim now, or None if they already claimed this
+    /// period. Missing a whole period starts the streak over.
+    pub fn next_streak(&self, last_claim: Option<(i64, i64)>, now: i64) -> Option<i64> {
+        let Some((last_claimed_at, streak)) = last_claim else {
+            return Some(1);
+        };
+
+        let last_period = self.period(last_claimed_at);
+        let period = self.period(now);
+
+        if period <= last_period {
+            None
+        } else if period == last_period + 1 {
+            Some(streak + 1)
+        } else {
+            Some(1)
+        }
+    }
+
+    pub fn reward(&self, streak: i64) -> i64 {
+        (self.base_reward() as f64 * streak_multiplier(streak)).round() as i64
+    }
+}
+
+pub fn streak_multiplier(streak: i64) -> f64 {
+    1.0 + (STREAK_BONUS * (streak - 1).max(0) as f64).min(MAX_STREAK_BONUS)
+}
+
+/// What happened when someone tried to claim a reward
+#[derive(Debug, Clone, Copy)]
+pub enum ClaimOutcome {
+    Claimed {
+        reward: i64,
+        streak: i64,
+        next_claim_at: i64,
+    },
+    TooSoon {
+        next_claim_at: i64,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Monday 2024-01-29 00:00:00 UTC
+    const MONDAY: i64 = 1_706_486_400;
+    // Wednesday 2024-01-31 23:59:59 UTC, the last second of January
+    const END_OF_JANUARY: i64 = 1_706_745_599;
+
+    #[test]
+    fn days_start_at_midnight_utc() {
+        let daily = ClaimKind::Daily;
+
+        assert_eq!(
+            daily.period(END_OF_JANUARY) + 1,
+            daily.period(END_OF_JANUARY + 1)
+        );
+        assert_eq!(
+            daily.period_start(daily.period(MONDAY + 12 * 60 * 60)),
+            MONDAY
+        );
+        assert_eq!(daily.next_claim_at(END_OF_JANUARY), END_OF_JANUARY + 1);
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        let weekly = ClaimKind::Weekly;
+
+        assert_eq!(weekly.period_start(weekly.period(MONDAY)), MONDAY);
+        assert_eq!(weekly.period(MONDAY - 1) + 1, weekly.period(MONDAY));
+        // Sunday night is still the same week
+        assert_eq!(weekly.period(MONDAY + WEEK - 1), weekly.period(MONDAY));
+        assert_eq!(weekly.next_claim_at(MONDAY + 3 * DAY), MONDAY + WEEK);
+    }
+
+    #[test]
+    fn weeks_carry_across_months() {
+        let weekly = ClaimKind::Weekly;
+
+        // February 1st 2024 is the Thursday of the week starting January 29th
+        assert_eq!(weekly.period(END_OF_JANUARY + 1), weekly.period(MONDAY));
+    }
+
+    #[test]
+    fn first_claim_starts_a_streak() {
+        assert_eq!(ClaimKind::Daily.next_streak(None, MONDAY), Some(1));
+    }
+
+    #[test]
+    fn claiming_twice_in_a_period_is_refused() {
+        let daily = ClaimKind::Daily;
+
+        assert_eq!(daily.next_streak(Some((MONDAY, 3)), MONDAY + DAY - 1), None);
+        assert_eq!(
+            ClaimKind::Weekly.next_streak(Some((MONDAY, 3)), MONDAY + WEEK - 1),
+            None
+        );
+    }
+
+    #[test]
+    fn claiming_the_next_period_continues_the_streak() {
+        // Just before midnight then just after still counts as the next day
+        assert_eq!(
+            ClaimKind::Daily.next_streak(Some((END_OF_JANUARY, 3)), END_OF_JANUARY + 1),
+            Some(4)
+        );
+        assert_eq!(
+            ClaimKind::Weekly.next_streak(Some((MONDAY, 3)), MONDAY + 2 * WEEK - 1),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn missing_a_period_resets_the_streak() {
+        assert_eq!(
+            ClaimKind::Daily.next_streak(Some((END_OF_JANUARY, 3)), END_OF_JANUARY + DAY + 1),
+            Some(1)
+        );
+        assert_eq!(
+            ClaimKind::Weekly.next_streak(Some((MONDAY, 3)), MONDAY + 2 * WEEK),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn streak_bonus_is_capped() {
+        assert_eq!(ClaimKind::Daily.reward(1), 100);
+        assert_eq!(ClaimKind::Daily.reward(5), 140);
+        assert_eq!(ClaimKind::Daily.reward(11), 200);
+        assert_eq!(ClaimKind::Daily.reward(50), 200);
+        assert_eq!(ClaimKind::Weekly.reward(2), 1100);
+    }
+}
@@ -1,4 +1,3 @@
-#![allow(clippy::derived_hash_with_manual_eq)]
 use crate::{
     built_info,
     custom_types::command::{Context, Error},
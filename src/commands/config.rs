@@ -1,11 +1,104 @@
-use poise::{CreateReply, serenity_prelude::ChannelId};
+use poise::{
+    CreateReply,
+    serenity_prelude::{ChannelId, Mentionable, Role},
+};
 use tracing::instrument;
 
 use crate::{
+    commands::gamble::{DEFAULT_HOUSE_EDGE, MAX_HOUSE_EDGE},
     custom_types::command::{Context, Error},
-    db::Database,
+    db::{Database, LevelUpMode},
+    levels::{DEFAULT_LEVEL_UP_MESSAGE, RewardRanges, XpCurve, render_level_up_message},
+    utils::message::send_simple_ephemeral,
 };
 
+#[poise::command(
+    slash_command,
+    guild_only,
+    subcommands(
+        "level_up",
+        "xp_curve",
+        "rewards",
+        "xp_rules",
+        "economy_admin_role",
+        "audit_channel",
+        "house"
+    ),
+    subcommand_required
+)]
+pub async fn config(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum XpCurveKind {
+    Logarithmic,
+    Linear,
+    Quadratic,
+    Table,
+}
+
+// Levels shown off after changing the curve
+const PREVIEW_LEVELS: [i64; 5] = [1, 5, 10, 25, 50];
+
+/// Choose where level up messages go and what they say
+#[poise::command(slash_command, required_permissions = "MANAGE_GUILD")]
+#[instrument(skip(ctx))]
+pub async fn level_up(
+    ctx: Context<'_>,
+    #[description = "Where level up messages go"] mode: LevelUpMode,
+    #[description = "The channel to send them to"] channel: Option<ChannelId>,
+    #[description = "Can use {user}, {old_level}, {new_level} and {vbucks}. \"default\" resets it."]
+    message: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let db = Database::new(ctx);
+
+    if mode == LevelUpMode::Channel && channel.is_none() {
+        let has_channel = match db.get_server_conf(guild_id).await {
+            Ok(conf) => conf.level_up_channel_id.is_some(),
+            Err(sqlx::Error::RowNotFound) => false,
+            Err(err) => return Err(err.into()),
+        };
+
+        if !has_channel {
+            send_simple_ephemeral(&ctx, "You need to pick a channel to send them to!").await?;
+            return Ok(());
+        }
+    }
+
+    let message = message.map(|m| {
+        if m == "default" {
+            String::from(DEFAULT_LEVEL_UP_MESSAGE)
+        } else {
+            m
+        }
+    });
+
+    db.save_level_up_config(guild_id, mode, channel, message.as_deref())
+        .await?;
+
+    let example = render_level_up_message(
+        message.as_deref().unwrap_or(DEFAULT_LEVEL_UP_MESSAGE),
+        ctx.author(),
+        4,
+        5,
+        1000,
+    );
+
+    ctx.send(
+        CreateReply::default()
+            .reply(true)
+            .ephemeral(true)
+            .content(format!(
+                "Level up messages will now look like this:\n{example}"
+            )),
+    )
+    .await?;
+
+    Ok(())
+}
+
 #[poise::command(slash_command)]
 #[instrument(skip(ctx))]
 pub async fn view_server_conf(ctx: Context<'_>) -> Result<(), Error> {
@@ -41,3 +134,216 @@ pub async fn set_error_channel(ctx: Context<'_>, channel_id: ChannelId) -> Resul
 
     Ok(())
 }
+
+/// Choose how much XP each level takes
+#[poise::command(slash_command, required_permissions = "MANAGE_GUILD")]
+#[instrument(skip(ctx))]
+pub async fn xp_curve(
+    ctx: Context<'_>,
+    #[description = "How XP needed grows with each level"] kind: XpCurveKind,
+    #[description = "XP per level for linear, or the multiplier of level² for quadratic"]
+    #[min = 1]
+    step: Option<u32>,
+    #[description = "Comma separated total XP needed for level 1, 2, 3 and so on"] table: Option<
+        String,
+    >,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+
+    let curve = match kind {
+        XpCurveKind::Logarithmic => Ok(XpCurve::Logarithmic),
+        XpCurveKind::Linear => step
+            .map(|step| XpCurve::Linear { step: step.into() })
+            .ok_or_else(|| String::from("You need to give a step for that curve!")),
+        XpCurveKind::Quadratic => step
+            .map(|step| XpCurve::Quadratic { step: step.into() })
+            .ok_or_else(|| String::from("You need to give a step for that curve!")),
+        XpCurveKind::Table => match table {
+            Some(table) => table
+                .split(',')
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(|l| {
+                    l.parse::<i64>()
+                        .map_err(|_| format!("{l} is not a number!"))
+                })
+                .collect::<Result<Vec<i64>, String>>()
+                .map(|levels| XpCurve::Table { levels }),
+            None => Err(String::from("You need to give a table for that curve!")),
+        },
+    }
+    .and_then(|curve| {
+        curve
+            .validate()
+            .map(|_| curve)
+            .map_err(|reason| format!("That curve won't work, {reason}!"))
+    });
+
+    let curve = match curve {
+        Ok(curve) => curve,
+        Err(reason) => {
+            send_simple_ephemeral(&ctx, &reason).await?;
+            return Ok(());
+        }
+    };
+
+    let db = Database::new(ctx);
+    db.save_xp_curve(guild_id, &curve).await?;
+
+    let preview = PREVIEW_LEVELS
+        .iter()
+        .map(|level| format!("Level {level}: {} XP", curve.exp_for_level(*level)))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    ctx.say(format!(
+        "Now using the {curve} XP curve. Use /level_role sync to fix everyone's level roles.\n{preview}"
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Choose how many V-Bucks and how much XP each message is worth
+#[poise::command(slash_command, required_permissions = "MANAGE_GUILD")]
+#[instrument(skip(ctx))]
+pub async fn rewards(
+    ctx: Context<'_>,
+    #[description = "Fewest V-Bucks a message can earn"] money_min: Option<u32>,
+    #[description = "Most V-Bucks a message can earn"] money_max: Option<u32>,
+    #[description = "Least XP a message can earn"] xp_min: Option<u32>,
+    #[description = "Most XP a message can earn"] xp_max: Option<u32>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let db = Database::new(ctx);
+
+    let old = match db.get_server_conf(guild_id).await {
+        Ok(conf) => RewardRanges::from_conf(Some(&conf)),
+        Err(sqlx::Error::RowNotFound) => RewardRanges::from_conf(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let money_min = money_min.map_or(*old.money.start(), i64::from);
+    let money_max = money_max.map_or(*old.money.end(), i64::from);
+    let exp_min = xp_min.map_or(*old.exp.start(), i64::from);
+    let exp_max = xp_max.map_or(*old.exp.end(), i64::from);
+
+    if money_min > money_max || exp_min > exp_max {
+        send_simple_ephemeral(&ctx, "The minimum can't be more than the maximum!").await?;
+        return Ok(());
+    }
+
+    db.save_reward_ranges(guild_id, money_min..=money_max, exp_min..=exp_max)
+        .await?;
+
+    ctx.say(format!(
+        "Messages are now worth {money_min} to {money_max} V-Bucks and {exp_min} to {exp_max} XP"
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Choose how often messages can earn XP and V-Bucks, and how long they have to be
+#[poise::command(slash_command, required_permissions = "MANAGE_GUILD")]
+#[instrument(skip(ctx))]
+pub async fn xp_rules(
+    ctx: Context<'_>,
+    #[description = "Seconds between messages that earn XP and V-Bucks"] cooldown: Option<u32>,
+    #[description = "Shortest message (in characters) that earns XP and V-Bucks"]
+    min_length: Option<u32>,
+) -> Result<(), Error> {
+    let db = Database::new(ctx);
+    db.save_xp_rules(
+        ctx.guild_id().expect("not used in DM"),
+        cooldown.map(i64::from),
+        min_length.map(i64::from),
+    )
+    .await?;
+
+    ctx.say("Updated XP rules!").await?;
+
+    Ok(())
+}
+
+/// Let a role use /economy. Anyone that can manage the server can always use it.
+#[poise::command(slash_command, required_permissions = "MANAGE_GUILD")]
+#[instrument(skip(ctx))]
+pub async fn economy_admin_role(
+    ctx: Context<'_>,
+    #[description = "The role, leave empty to remove it"] role: Option<Role>,
+) -> Result<(), Error> {
+    let db = Database::new(ctx);
+    db.save_economy_admin_role(
+        ctx.guild_id().expect("not used in DM"),
+        role.as_ref().map(|r| r.id),
+    )
+    .await?;
+
+    ctx.say(match role {
+        Some(role) => format!("{} can now use /economy", role.mention()),
+        None => String::from("Only people that can manage the server can use /economy now"),
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Choose where /economy changes get announced. By default they go to the error channel.
+#[poise::command(slash_command, required_permissions = "MANAGE_GUILD")]
+#[instrument(skip(ctx))]
+pub async fn audit_channel(
+    ctx: Context<'_>,
+    #[description = "The channel, leave empty to use the error channel"] channel: Option<ChannelId>,
+) -> Result<(), Error> {
+    let db = Database::new(ctx);
+    db.save_audit_channel(ctx.guild_id().expect("not used in DM"), channel)
+        .await?;
+
+    ctx.say(match channel {
+        Some(channel) => format!("Economy changes will be announced in {}", channel.mention()),
+        None => String::from("Economy changes will be announced in the error channel"),
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Set up Johnson's bankroll and cut for every game played against the house
+#[poise::command(slash_command, required_permissions = "MANAGE_GUILD")]
+#[instrument(skip(ctx))]
+pub async fn house(
+    ctx: Context<'_>,
+    #[description = "V-Bucks Johnson has to pay winners with"] bankroll: Option<u32>,
+    #[description = "Percent Johnson keeps from what he pays out"]
+    #[max = 50]
+    edge: Option<u32>,
+    #[description = "Should Johnson learn what members like to throw in Rock Paper Scissors and counter it?"]
+    adaptive: Option<bool>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let db = Database::new(ctx);
+
+    db.save_house_config(
+        guild_id,
+        bankroll.map(i64::from),
+        edge.map(|e| i64::from(e).min(MAX_HOUSE_EDGE)),
+        adaptive,
+    )
+    .await?;
+
+    let conf = db.get_server_conf(guild_id).await?;
+    ctx.say(format!(
+        "Johnson has ${} in the bank, keeps {}% of winnings and {} in Rock Paper Scissors",
+        conf.house_bankroll,
+        conf.house_edge.unwrap_or(DEFAULT_HOUSE_EDGE),
+        if conf.rps_adaptive {
+            "counters what members like to throw"
+        } else {
+            "throws at random"
+        }
+    ))
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,627 @@
+use std::time::Duration;
+
+use poise::serenity_prelude::{
+    ChannelId, CreateActionRow, CreateButton, CreateEmbed, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, Mentionable, RoleId, Timestamp, User, UserId,
+};
+use poise::{ChoiceParameter, CreateReply};
+use tracing::{info, instrument, warn};
+
+use crate::claims::{ClaimKind, ClaimOutcome, streak_multiplier};
+use crate::custom_types::command::{Context, Error};
+use crate::custom_types::mongo_schema::LedgerEntry;
+use crate::db::{BalanceUpdate, Currency, Database, LedgerReason, RelationType, TransferError};
+use crate::levels::sync_level_roles;
+use crate::utils::message::embed::base_embed;
+use crate::utils::message::interaction::{page_buttons, wait_for_user_interaction};
+use crate::utils::message::send_simple_ephemeral;
+
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_NOTE_LEN: usize = 200;
+const HISTORY_PAGE_SIZE: i64 = 10;
+const HISTORY_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Claim your V-Bucks for the day
+#[poise::command(slash_command, guild_only)]
+#[instrument(skip(ctx))]
+pub async fn daily(ctx: Context<'_>) -> Result<(), Error> {
+    claim(ctx, ClaimKind::Daily).await
+}
+
+/// Claim your V-Bucks for the week
+#[poise::command(slash_command, guild_only)]
+#[instrument(skip(ctx))]
+pub async fn weekly(ctx: Context<'_>) -> Result<(), Error> {
+    claim(ctx, ClaimKind::Weekly).await
+}
+
+async fn claim(ctx: Context<'_>, kind: ClaimKind) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let author = ctx.author();
+    let db = Database::new(ctx);
+
+    // Makes sure they are a member before claiming
+    db.get_user(guild_id, author).await?;
+
+    let now = Timestamp::now().unix_timestamp();
+    match db.claim_reward(guild_id, author, kind, now).await? {
+        ClaimOutcome::Claimed {
+            reward,
+            streak,
+            next_claim_at,
+        } => {
+            info!(reward, streak, "Claimed {kind:?} reward");
+
+            let embed = base_embed()
+                .title(format!("{kind:?} Reward"))
+                .description(format!("You got **{reward}** V-Bucks!"))
+                .field(
+                    "Streak",
+                    format!("{streak} (x{:.1})", streak_multiplier(streak)),
+                    true,
+                )
+                .field("Next Claim", format!("<t:{next_claim_at}:R>"), true);
+
+            ctx.send(CreateReply::default().embed(embed).reply(true))
+                .await?;
+        }
+        ClaimOutcome::TooSoon { next_claim_at } => {
+            send_simple_ephemeral(
+                &ctx,
+                &format!("You already claimed this one! Come back <t:{next_claim_at}:R>"),
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Send some of your V-Bucks to someone else
+#[poise::command(slash_command, guild_only)]
+#[instrument(skip(ctx))]
+pub async fn pay(
+    ctx: Context<'_>,
+    #[description = "Who you're paying"] user: User,
+    #[description = "How many V-Bucks to send"] amount: u32,
+    #[description = "What it's for"] note: Option<String>,
+    #[description = "DM them a receipt? By default this is yes."] receipt: Option<bool>,
+) -> Result<(), Error> {
+    send_vbucks(ctx, user, amount, note, receipt, LedgerReason::Payment).await
+}
+
+/// Give some of your V-Bucks away to someone else
+#[poise::command(slash_command, guild_only)]
+#[instrument(skip(ctx))]
+pub async fn gift(
+    ctx: Context<'_>,
+    #[description = "Who you're gifting"] user: User,
+    #[description = "How many V-Bucks to give"] amount: u32,
+    #[description = "A message to go with it"] note: Option<String>,
+    #[description = "DM them a receipt? By default this is yes."] receipt: Option<bool>,
+) -> Result<(), Error> {
+    send_vbucks(ctx, user, amount, note, receipt, LedgerReason::Gift).await
+}
+
+async fn send_vbucks(
+    ctx: Context<'_>,
+    recipient: User,
+    amount: u32,
+    note: Option<String>,
+    receipt: Option<bool>,
+    reason: LedgerReason,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let author = ctx.author();
+    let verb = match reason {
+        LedgerReason::Gift => "gift",
+        _ => "pay",
+    };
+
+    if recipient.id == author.id {
+        send_simple_ephemeral(&ctx, &format!("You can't {verb} yourself!")).await?;
+        return Ok(());
+    }
+
+    if recipient.bot {
+        send_simple_ephemeral(&ctx, "Bots don't need V-Bucks!").await?;
+        return Ok(());
+    }
+
+    if amount == 0 {
+        send_simple_ephemeral(&ctx, "You have to send something!").await?;
+        return Ok(());
+    }
+
+    let note = note.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+    if note
+        .as_ref()
+        .is_some_and(|n| n.chars().count() > MAX_NOTE_LEN)
+    {
+        send_simple_ephemeral(
+            &ctx,
+            &format!("Notes can't be longer than {MAX_NOTE_LEN} characters!"),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let db = Database::new(ctx);
+
+    if db.get_relation(&recipient, author).await? == Some(RelationType::Blocked) {
+        send_simple_ephemeral(
+            &ctx,
+            &format!(
+                "{} has you blocked, they don't want your money",
+                recipient.name
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let db_author = db.get_user(guild_id, author).await?;
+    if db_author.vbucks < amount.into() {
+        send_simple_ephemeral(&ctx, "You don't have enough V-Bucks for that!").await?;
+        return Ok(());
+    }
+
+    // Makes sure they are a member before any money goes their way
+    db.get_user(guild_id, &recipient).await?;
+
+    let confirm_embed = transfer_embed(author, &recipient, amount, note.as_deref())
+        .title("Are you sure?")
+        .field(
+            "Your Balance Afterwards",
+            (db_author.vbucks - i64::from(amount)).to_string(),
+            false,
+        );
+
+    let reply = ctx
+        .send(
+            CreateReply::default()
+                .embed(confirm_embed.clone())
+                .components(vec![CreateActionRow::Buttons(vec![
+                    CreateButton::new("confirm").label("Send"),
+                    CreateButton::new("cancel").label("Cancel"),
+                ])])
+                .ephemeral(true),
+        )
+        .await?;
+    let message = reply.message().await?.into_owned();
+
+    let interaction = wait_for_user_interaction(&ctx, &message, author.id, CONFIRM_TIMEOUT).await;
+
+    let status = match &interaction {
+        Some(int) if int.data.custom_id == "confirm" => None,
+        Some(_) => Some("Cancelled"),
+        None => Some("Timed out"),
+    };
+
+    if let Some(status) = status {
+        match interaction {
+            // The cancel press has to be answered or they'll be told the interaction failed
+            Some(interaction) => {
+                interaction
+                    .create_response(
+                        ctx,
+                        CreateInteractionResponse::UpdateMessage(
+                            CreateInteractionResponseMessage::new()
+                                .embed(confirm_embed.title(status))
+                                .components(Vec::new()),
+                        ),
+                    )
+                    .await?;
+            }
+            None => {
+                reply
+                    .edit(
+                        ctx,
+                        CreateReply::default()
+                            .embed(confirm_embed.title(status))
+                            .components(Vec::new()),
+                    )
+                    .await?;
+            }
+        }
+        return Ok(());
+    }
+
+    let result = db
+        .user_transaction(
+            guild_id,
+            author,
+            &recipient,
+            amount.into(),
+            reason,
+            note.as_deref(),
+        )
+        .await;
+
+    let (status, sent) = match result {
+        Ok(()) => ("Sent!", true),
+        // They spent it while deciding
+        Err(TransferError::InsufficientFunds { .. }) => {
+            ("You don't have enough V-Bucks anymore!", false)
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    if let Some(interaction) = interaction {
+        interaction
+            .create_response(
+                ctx,
+                CreateInteractionResponse::UpdateMessage(
+                    CreateInteractionResponseMessage::new()
+                        .embed(confirm_embed.title(status))
+                        .components(Vec::new()),
+                ),
+            )
+            .await?;
+    }
+
+    if !sent {
+        return Ok(());
+    }
+
+    info!(amount, reason = reason.as_str(), "Sent V-Bucks");
+
+    let note_line = note.as_ref().map_or(String::new(), |n| format!("\n> {n}"));
+    ctx.say(format!(
+        "{} {} {} {amount} V-Bucks!{note_line}",
+        author.mention(),
+        match reason {
+            LedgerReason::Gift => "gifted",
+            _ => "paid",
+        },
+        recipient.mention()
+    ))
+    .await?;
+
+    if receipt.unwrap_or(true) {
+        let receipt = transfer_embed(author, &recipient, amount, note.as_deref())
+            .title("You got V-Bucks!")
+            .field(
+                "Server",
+                guild_id
+                    .name(ctx)
+                    .unwrap_or_else(|| String::from("Unknown")),
+                false,
+            );
+
+        // Plenty of people have their DMs closed, that's not worth failing over
+        if let Err(err) = recipient
+            .direct_message(ctx, CreateMessage::new().embed(receipt))
+            .await
+        {
+            warn!("Couldn't DM receipt to {}: {err}", recipient.name);
+        }
+    }
+
+    Ok(())
+}
+
+fn transfer_embed(from: &User, to: &User, amount: u32, note: Option<&str>) -> CreateEmbed {
+    let embed = base_embed()
+        .field("From", from.mention().to_string(), true)
+        .field("To", to.mention().to_string(), true)
+        .field("Amount", format!("{amount} V-Bucks"), true);
+
+    match note {
+        Some(note) => embed.field("Note", note, false),
+        None => embed,
+    }
+}
+
+/// See where your V-Bucks came from and where they went
+#[poise::command(slash_command, guild_only)]
+#[instrument(skip(ctx))]
+pub async fn history(ctx: Context<'_>) -> Result<(), Error> {
+    show_history(ctx, ctx.author()).await
+}
+
+/// See where anyone's V-Bucks came from and where they went
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+#[instrument(skip(ctx))]
+pub async fn history_of(
+    ctx: Context<'_>,
+    #[description = "Whose history to look at"] user: User,
+) -> Result<(), Error> {
+    show_history(ctx, &user).await
+}
+
+async fn show_history(ctx: Context<'_>, user: &User) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let db = Database::new(ctx);
+
+    let entry_count = db.get_ledger_count(guild_id, user).await?;
+    if entry_count == 0 {
+        send_simple_ephemeral(&ctx, &format!("{} has no V-Bucks history", user.name)).await?;
+        return Ok(());
+    }
+
+    let page_count = (entry_count + HISTORY_PAGE_SIZE - 1) / HISTORY_PAGE_SIZE;
+    let mut page = 0;
+
+    let entries = db.get_ledger(guild_id, user, HISTORY_PAGE_SIZE, 0).await?;
+    let mut embed = history_embed(user, &entries, page, page_count);
+
+    let reply = ctx
+        .send(
+            CreateReply::default()
+                .embed(embed.clone())
+                .components(page_buttons(page, page_count))
+                .ephemeral(true),
+        )
+        .await?;
+    let message = reply.message().await?.into_owned();
+
+    while let Some(interaction) =
+        wait_for_user_interaction(&ctx, &message, ctx.author().id, HISTORY_TIMEOUT).await
+    {
+        match interaction.data.custom_id.as_str() {
+            "prev" => page = (page - 1).max(0),
+            "next" => page = (page + 1).min(page_count - 1),
+            _ => {}
+        }
+
+        let entries = db
+            .get_ledger(guild_id, user, HISTORY_PAGE_SIZE, page * HISTORY_PAGE_SIZE)
+            .await?;
+        embed = history_embed(user, &entries, page, page_count);
+
+        interaction
+            .create_response(
+                ctx,
+                CreateInteractionResponse::UpdateMessage(
+                    CreateInteractionResponseMessage::new()
+                        .embed(embed.clone())
+                        .components(page_buttons(page, page_count)),
+                ),
+            )
+            .await?;
+    }
+
+    reply
+        .edit(
+            ctx,
+            CreateReply::default().embed(embed).components(Vec::new()),
+        )
+        .await?;
+
+    Ok(())
+}
+
+fn history_embed(user: &User, entries: &[LedgerEntry], page: i64, page_count: i64) -> CreateEmbed {
+    let lines = entries
+        .iter()
+        .map(|entry| {
+            let reason = LedgerReason::from_db(&entry.reason)
+                .map_or(entry.reason.clone(), |r| r.to_string());
+            let counterparty = entry.counterparty.map_or(String::new(), |id| {
+                let direction = if entry.reason == LedgerReason::Admin.as_str() {
+                    "by"
+                } else if entry.amount < 0 {
+                    "to"
+                } else {
+                    "from"
+                };
+                format!(" {direction} {}", UserId::new(id as u64).mention())
+            });
+            let unit = if entry.currency == Currency::Exp.as_str() {
+                "XP"
+            } else {
+                "V-Bucks"
+            };
+
+            format!(
+                "<t:{}:d> **{:+} {unit}** {reason}{counterparty}",
+                entry.created_at, entry.amount
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    base_embed()
+        .title(format!("{}'s History", user.name))
+        .description(lines)
+        .field("Page", format!("{} / {page_count}", page + 1), false)
+}
+
+/// Lets through anyone that can manage the server or has the server's economy admin role. Checks
+/// on a parent command also run for its subcommands.
+async fn economy_admin(ctx: Context<'_>) -> Result<bool, Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(false);
+    };
+    let Some(member) = ctx.author_member().await else {
+        return Ok(false);
+    };
+
+    if member.permissions.is_some_and(|p| p.manage_guild()) {
+        return Ok(true);
+    }
+
+    let admin_role = match Database::new(ctx).get_server_conf(guild_id).await {
+        Ok(conf) => conf.economy_admin_role_id,
+        Err(sqlx::Error::RowNotFound) => None,
+        Err(err) => return Err(err.into()),
+    };
+
+    if admin_role.is_some_and(|r| member.roles.contains(&RoleId::new(r as u64))) {
+        return Ok(true);
+    }
+
+    send_simple_ephemeral(&ctx, "Only economy admins can do that!").await?;
+    Ok(false)
+}
+
+#[poise::command(
+    slash_command,
+    guild_only,
+    subcommands("set", "add", "remove", "reset"),
+    subcommand_required,
+    check = "economy_admin"
+)]
+pub async fn economy(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Set someone's V-Bucks or XP
+#[poise::command(slash_command)]
+#[instrument(skip(ctx))]
+pub async fn set(
+    ctx: Context<'_>,
+    #[description = "Whose balance to change"] user: User,
+    #[description = "What to change"] currency: Currency,
+    #[description = "What it should be"] amount: u32,
+    #[description = "Why, for the audit log"] reason: Option<String>,
+) -> Result<(), Error> {
+    admin_update(
+        ctx,
+        user,
+        currency,
+        BalanceUpdate::Set(amount.into()),
+        reason,
+    )
+    .await
+}
+
+/// Give someone V-Bucks or XP
+#[poise::command(slash_command)]
+#[instrument(skip(ctx))]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "Whose balance to change"] user: User,
+    #[description = "What to give"] currency: Currency,
+    #[description = "How much to give"] amount: u32,
+    #[description = "Why, for the audit log"] reason: Option<String>,
+) -> Result<(), Error> {
+    admin_update(
+        ctx,
+        user,
+        currency,
+        BalanceUpdate::Add(amount.into()),
+        reason,
+    )
+    .await
+}
+
+/// Take V-Bucks or XP away from someone
+#[poise::command(slash_command)]
+#[instrument(skip(ctx))]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "Whose balance to change"] user: User,
+    #[description = "What to take"] currency: Currency,
+    #[description = "How much to take"] amount: u32,
+    #[description = "Why, for the audit log"] reason: Option<String>,
+) -> Result<(), Error> {
+    admin_update(
+        ctx,
+        user,
+        currency,
+        BalanceUpdate::Add(-i64::from(amount)),
+        reason,
+    )
+    .await
+}
+
+/// Set someone's V-Bucks and XP back to 0
+#[poise::command(slash_command)]
+#[instrument(skip(ctx))]
+pub async fn reset(
+    ctx: Context<'_>,
+    #[description = "Whose balance to reset"] user: User,
+    #[description = "Only reset this. By default both are reset."] currency: Option<Currency>,
+    #[description = "Why, for the audit log"] reason: Option<String>,
+) -> Result<(), Error> {
+    let currencies = match currency {
+        Some(currency) => vec![currency],
+        None => vec![Currency::VBucks, Currency::Exp],
+    };
+
+    for currency in currencies {
+        admin_update(
+            ctx,
+            user.clone(),
+            currency,
+            BalanceUpdate::Set(0),
+            reason.clone(),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn admin_update(
+    ctx: Context<'_>,
+    user: User,
+    currency: Currency,
+    update: BalanceUpdate,
+    reason: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let db = Database::new(ctx);
+
+    if user.bot {
+        send_simple_ephemeral(&ctx, "Bots don't have balances!").await?;
+        return Ok(());
+    }
+
+    // Makes sure they are a member before changing anything
+    db.get_user(guild_id, &user).await?;
+    let (old, new) = db
+        .admin_update_balance(guild_id, &user, currency, update, ctx.author())
+        .await?;
+
+    let name = currency.name();
+    ctx.say(format!(
+        "{}'s {name} went from {old} to {new}",
+        user.mention()
+    ))
+    .await?;
+
+    // Levels might have changed, so their level roles have to catch up
+    if currency == Currency::Exp {
+        let curve = db.get_xp_curve(guild_id).await?;
+        match guild_id.member(ctx, user.id).await {
+            Ok(member) => {
+                sync_level_roles(ctx.http(), &db, &member, curve.level(new)).await?;
+            }
+            Err(err) => warn!("Couldn't sync level roles for {}: {err}", user.name),
+        }
+    }
+
+    let conf = match db.get_server_conf(guild_id).await {
+        Ok(conf) => Some(conf),
+        Err(sqlx::Error::RowNotFound) => None,
+        Err(err) => return Err(err.into()),
+    };
+
+    let Some(audit_channel) = conf.and_then(|c| c.audit_channel_id.or(c.error_channel_id)) else {
+        return Ok(());
+    };
+
+    let embed = base_embed()
+        .title("Economy Change")
+        .field("Admin", ctx.author().mention().to_string(), true)
+        .field("Member", user.mention().to_string(), true)
+        .field(name, format!("{old} -> {new}"), true)
+        .field(
+            "Reason",
+            reason.unwrap_or_else(|| String::from("None given")),
+            false,
+        );
+
+    // The change already happened, a missing channel shouldn't make it look like it failed
+    if let Err(err) = ChannelId::new(audit_channel as u64)
+        .send_message(ctx, CreateMessage::new().embed(embed))
+        .await
+    {
+        warn!("Couldn't send to audit channel: {err}");
+    }
+
+    Ok(())
+}
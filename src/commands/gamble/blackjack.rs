@@ -0,0 +1,269 @@
+use poise::serenity_prelude::{
+    Color, CreateActionRow, CreateButton, CreateEmbed, CreateEmbedFooter,
+    CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+use poise::{CreateReply, ReplyHandle};
+use rand::Rng;
+use tracing::{info, instrument};
+
+use super::session::{Escrow, TURN_TIMEOUT};
+use crate::blackjack::{Action, DECKS_IN_SHOE, Game, HandOutcome, Shoe};
+use crate::custom_types::command::{Context, Error};
+use crate::db::{Database, LedgerReason};
+use crate::utils::message::embed::base_embed;
+use crate::utils::message::interaction::wait_for_user_interaction;
+use crate::utils::message::send_simple_ephemeral;
+
+const ACTIONS: [(Action, &str, &str); 4] = [
+    (Action::Hit, "hit", "Hit"),
+    (Action::Stand, "stand", "Stand"),
+    (Action::Double, "double", "Double"),
+    (Action::Split, "split", "Split"),
+];
+
+/// Play a round of blackjack against Johnson
+#[poise::command(slash_command, guild_only)]
+#[instrument(skip(ctx))]
+pub async fn blackjack(
+    ctx: Context<'_>,
+    #[description = "How many V-Bucks you're betting"] bet: u32,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let author = ctx.author();
+
+    if bet == 0 {
+        send_simple_ephemeral(&ctx, "You have to bet something!").await?;
+        return Ok(());
+    }
+
+    let bankroll = match Database::new(ctx).get_server_conf(guild_id).await {
+        Ok(conf) => conf.house_bankroll,
+        Err(sqlx::Error::RowNotFound) => 0,
+        Err(err) => return Err(err.into()),
+    };
+    if bankroll < HandOutcome::Blackjack.net(bet) {
+        send_simple_ephemeral(
+            &ctx,
+            &format!("Johnson can't cover that bet, he only has ${bankroll} in the bank!"),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    // Bets are held until the round is over, doubling and splitting add to them
+    let escrow = Escrow::open(ctx, guild_id, "blackjack").await?;
+    match escrow.hold(author, bet, LedgerReason::BlackjackBet).await {
+        Ok(true) => {}
+        Ok(false) => {
+            escrow.refund().await?;
+            send_simple_ephemeral(&ctx, "You do not have enough money for this bet!").await?;
+            return Ok(());
+        }
+        Err(err) => {
+            escrow.refund().await?;
+            return Err(err);
+        }
+    }
+
+    let seed = ctx.data().rng.with(|rng| rng.random::<u64>());
+    info!(seed, "Dealing blackjack");
+    let mut game = Game::deal(bet, Shoe::new(DECKS_IN_SHOE, seed));
+
+    let reply = match play_hands(ctx, &escrow, &mut game, seed, bankroll).await {
+        Ok(reply) => reply,
+        Err(err) => {
+            escrow.refund().await?;
+            return Err(err);
+        }
+    };
+
+    let outcomes = game.finish();
+    let returned = game.total_returned(&outcomes);
+    let staked = game.staked();
+    info!(outcomes = ?outcomes, returned, staked, "Finished blackjack");
+
+    let net = returned - staked;
+    let settled = escrow
+        .settle_with_house(author, net, LedgerReason::BlackjackPayout)
+        .await?;
+
+    let result_embed = game_embed(&game, seed, Some(&outcomes));
+    let result_embed = match net.signum() {
+        _ if settled.is_none() => result_embed.field(
+            "Result",
+            format!("Johnson went broke before he could pay up, you get your ${staked} back!"),
+            false,
+        ),
+        1 => result_embed
+            .field("Result", format!("You won ${net}!"), false)
+            .color(Color::DARK_GREEN),
+        -1 => result_embed
+            .field("Result", format!("You lost ${}", -net), false)
+            .color(Color::RED),
+        _ => result_embed.field("Result", "You broke even", false),
+    };
+
+    reply
+        .edit(
+            ctx,
+            CreateReply::default()
+                .embed(result_embed)
+                .components(Vec::new()),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Lets the player act on their hands until they're all done, holding more of their V-Bucks
+/// whenever they double or split. Stakes the bankroll couldn't pay out if every hand won are
+/// refused. Any hands left when they stop answering are stood.
+async fn play_hands<'a>(
+    ctx: Context<'a>,
+    escrow: &Escrow<'_>,
+    game: &mut Game,
+    seed: u64,
+    bankroll: i64,
+) -> Result<ReplyHandle<'a>, Error> {
+    let author = ctx.author();
+    let reply = ctx
+        .send(
+            CreateReply::default()
+                .embed(game_embed(game, seed, None))
+                .components(action_buttons(game)),
+        )
+        .await?;
+    let message = reply.message().await?.into_owned();
+
+    while !game.is_over() {
+        let Some(interaction) =
+            wait_for_user_interaction(&ctx, &message, author.id, TURN_TIMEOUT).await
+        else {
+            info!("Blackjack timed out, standing every hand");
+            break;
+        };
+
+        let Some((action, _, label)) = ACTIONS
+            .iter()
+            .find(|(_, id, _)| *id == interaction.data.custom_id)
+        else {
+            continue;
+        };
+
+        let stake = game.extra_stake(*action);
+        let problem = if !game.can(*action) {
+            Some(format!("You can't {} right now!", label.to_lowercase()))
+        } else if stake > 0 && game.staked() + i64::from(stake) > bankroll {
+            Some(format!(
+                "Johnson can't cover that, he only has ${bankroll} in the bank!"
+            ))
+        } else if stake > 0
+            && !escrow
+                .hold(author, stake, LedgerReason::BlackjackBet)
+                .await?
+        {
+            Some(format!(
+                "You can't afford to {}, it takes another ${stake}!",
+                label.to_lowercase()
+            ))
+        } else {
+            None
+        };
+
+        if let Some(problem) = problem {
+            let _ = interaction
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new()
+                            .content(problem)
+                            .ephemeral(true),
+                    ),
+                )
+                .await;
+            continue;
+        }
+
+        game.apply(*action)?;
+
+        interaction
+            .create_response(
+                ctx,
+                CreateInteractionResponse::UpdateMessage(
+                    CreateInteractionResponseMessage::new()
+                        .embed(game_embed(game, seed, None))
+                        .components(action_buttons(game)),
+                ),
+            )
+            .await?;
+    }
+
+    Ok(reply)
+}
+
+fn action_buttons(game: &Game) -> Vec<CreateActionRow> {
+    if game.is_over() {
+        return Vec::new();
+    }
+
+    vec![CreateActionRow::Buttons(
+        ACTIONS
+            .iter()
+            .map(|(action, id, label)| {
+                CreateButton::new(*id)
+                    .label(*label)
+                    .disabled(!game.can(*action))
+            })
+            .collect(),
+    )]
+}
+
+/// Shows every hand, keeping the dealer's second card and the shoe seed hidden until there are
+/// outcomes to show
+fn game_embed(game: &Game, seed: u64, outcomes: Option<&[HandOutcome]>) -> CreateEmbed {
+    let dealer = match outcomes {
+        Some(_) => game.dealer.to_string(),
+        None => format!("{} ??", game.dealer.cards[0]),
+    };
+
+    let mut embed = base_embed()
+        .title("Blackjack")
+        .field("Dealer", dealer, false);
+
+    for (i, hand) in game.hands.iter().enumerate() {
+        let name = if game.hands.len() == 1 {
+            String::from("Your Hand")
+        } else {
+            format!("Hand {}", i + 1)
+        };
+        let marker = if game.active_hand() == Some(i) {
+            "▶ "
+        } else {
+            ""
+        };
+
+        let mut value = format!("{}\nBet: ${}", hand.hand, hand.bet);
+        if let Some(outcome) = outcomes.and_then(|o| o.get(i)) {
+            value.push('\n');
+            value.push_str(&outcome_text(*outcome, hand.bet));
+        }
+
+        embed = embed.field(format!("{marker}{name}"), value, true);
+    }
+
+    // Anyone with the seed can rebuild the shoe, so it's only shown once there's nothing to see
+    match outcomes {
+        Some(_) => embed.footer(CreateEmbedFooter::new(format!("Shoe seed: {seed}"))),
+        None => embed,
+    }
+}
+
+fn outcome_text(outcome: HandOutcome, bet: u32) -> String {
+    let net = outcome.net(bet);
+    match outcome {
+        HandOutcome::Blackjack => format!("Blackjack! +${net}"),
+        HandOutcome::Win => format!("Won ${net}"),
+        HandOutcome::Push => String::from("Push"),
+        HandOutcome::Loss => format!("Lost ${}", -net),
+    }
+}
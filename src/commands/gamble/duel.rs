@@ -0,0 +1,408 @@
+use poise::serenity_prelude::{Mentionable, User};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Ordering;
+use tracing::{debug, instrument};
+
+use super::session::{
+    Challenge, Game, GameResult, Player, challenge, hold_house_wager, house_terms, johnson,
+    play_for_wager, settle_with_house,
+};
+use crate::custom_types::command::{Context, Error};
+use crate::db::{Database, LedgerReason};
+use crate::utils::message::{send_simple_ephemeral, simple_channel_message};
+
+const DEFAULT_SIDES: u32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Coin {
+    Heads,
+    Tails,
+}
+
+fn flip(rng: &mut impl Rng) -> Coin {
+    if rng.random_bool(0.5) {
+        Coin::Heads
+    } else {
+        Coin::Tails
+    }
+}
+
+fn roll(rng: &mut impl Rng, sides: u32) -> u32 {
+    rng.random_range(1..=sides)
+}
+
+/// The first player always calls heads
+fn coinflip_result(coin: Coin) -> GameResult {
+    match coin {
+        Coin::Heads => GameResult::Winner(0),
+        Coin::Tails => GameResult::Winner(1),
+    }
+}
+
+/// Highest roll wins, matching rolls are a draw
+fn dice_result(rolls: [u32; 2]) -> GameResult {
+    match rolls[0].cmp(&rolls[1]) {
+        Ordering::Greater => GameResult::Winner(0),
+        Ordering::Less => GameResult::Winner(1),
+        Ordering::Equal => GameResult::Draw,
+    }
+}
+
+/// A single coin flip. Takes its RNG so the flip can be made predictable.
+struct Coinflip<R> {
+    rng: R,
+}
+
+impl<R: Rng> Coinflip<R> {
+    fn toss(&mut self) -> (Coin, GameResult) {
+        let coin = flip(&mut self.rng);
+        (coin, coinflip_result(coin))
+    }
+}
+
+impl<R: Rng + Send> Game for Coinflip<R> {
+    const KEY: &'static str = "coinflip";
+    const WAGER_REASON: LedgerReason = LedgerReason::CoinflipWager;
+    const WIN_REASON: LedgerReason = LedgerReason::CoinflipWin;
+
+    async fn play(
+        &mut self,
+        ctx: &Context<'_>,
+        players: [&Player; 2],
+    ) -> Result<GameResult, Error> {
+        let (coin, result) = self.toss();
+
+        simple_channel_message(
+            ctx,
+            &format!(
+                "🪙 {} called heads and the coin landed on **{coin:?}**!",
+                players[0].nick
+            ),
+        )
+        .await?;
+
+        Ok(result)
+    }
+}
+
+/// Both players roll a die once. Takes its RNG so the rolls can be made predictable.
+struct DiceDuel<R> {
+    rng: R,
+    sides: u32,
+}
+
+impl<R: Rng> DiceDuel<R> {
+    fn roll_both(&mut self) -> ([u32; 2], GameResult) {
+        let rolls = [
+            roll(&mut self.rng, self.sides),
+            roll(&mut self.rng, self.sides),
+        ];
+        (rolls, dice_result(rolls))
+    }
+}
+
+impl<R: Rng + Send> Game for DiceDuel<R> {
+    const KEY: &'static str = "dice";
+    const WAGER_REASON: LedgerReason = LedgerReason::DiceWager;
+    const WIN_REASON: LedgerReason = LedgerReason::DiceWin;
+
+    async fn play(
+        &mut self,
+        ctx: &Context<'_>,
+        players: [&Player; 2],
+    ) -> Result<GameResult, Error> {
+        let (rolls, result) = self.roll_both();
+
+        simple_channel_message(
+            ctx,
+            &format!(
+                "🎲 {} rolled **{}** and {} rolled **{}**!",
+                players[0].nick, rolls[0], players[1].nick, rolls[1]
+            ),
+        )
+        .await?;
+
+        Ok(result)
+    }
+}
+
+/// Flip a coin for V-Bucks, calling heads
+#[poise::command(slash_command, guild_only)]
+#[instrument(skip(ctx))]
+pub async fn coinflip(
+    ctx: Context<'_>,
+    #[description = "What you're willing to wager"] wager: u32,
+    #[description = "Who you're challenging. Leave this out to play against Johnson."]
+    opponent: Option<User>,
+) -> Result<(), Error> {
+    let game = Coinflip {
+        rng: ctx.data().rng.with(StdRng::from_rng),
+    };
+
+    duel(
+        ctx,
+        game,
+        wager,
+        opponent,
+        "a Coinflip",
+        LedgerReason::CoinflipHouse,
+    )
+    .await
+}
+
+/// Roll dice for V-Bucks, the highest roll wins
+#[poise::command(slash_command, guild_only)]
+#[instrument(skip(ctx))]
+pub async fn dice(
+    ctx: Context<'_>,
+    #[description = "What you're willing to wager"] wager: u32,
+    #[description = "Who you're challenging. Leave this out to play against Johnson."]
+    opponent: Option<User>,
+    #[description = "How many sides the dice have. By default this is 6."]
+    #[min = 2]
+    #[max = 100]
+    sides: Option<u32>,
+) -> Result<(), Error> {
+    let sides = sides.unwrap_or(DEFAULT_SIDES);
+    let game = DiceDuel {
+        rng: ctx.data().rng.with(StdRng::from_rng),
+        sides,
+    };
+
+    duel(
+        ctx,
+        game,
+        wager,
+        opponent,
+        &format!("a Dice Duel (d{sides})"),
+        LedgerReason::DiceHouse,
+    )
+    .await
+}
+
+/// Plays a game against the opponent once they accept, or against Johnson if there isn't one
+async fn duel<G: Game>(
+    ctx: Context<'_>,
+    mut game: G,
+    wager: u32,
+    opponent: Option<User>,
+    name: &str,
+    house_reason: LedgerReason,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let author = ctx.author();
+
+    let opponent = match opponent {
+        Some(opponent) if opponent.id != ctx.framework().bot_id => opponent,
+        _ => return play_against_house(ctx, game, wager, name, house_reason).await,
+    };
+
+    if opponent.bot {
+        ctx.say("You can't play with a bot!").await?;
+        return Ok(());
+    }
+
+    if opponent.id == author.id {
+        send_simple_ephemeral(&ctx, "You can't challenge yourself!").await?;
+        return Ok(());
+    }
+
+    let driver = Database::new(ctx);
+
+    debug!("Checking for author money");
+    if driver.get_user(guild_id, author).await?.vbucks < wager.into() {
+        send_simple_ephemeral(&ctx, "You do not have enough money for this wager!").await?;
+        return Ok(());
+    }
+
+    debug!("Checking for opponent money");
+    if driver.get_user(guild_id, &opponent).await?.vbucks < wager.into() {
+        send_simple_ephemeral(
+            &ctx,
+            &format!("{opponent} does not have enough money for this wager!"),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    ctx.defer_ephemeral().await?;
+
+    let author_player = Player::new(&ctx, guild_id, author.clone()).await;
+    let opponent_player = Player::new(&ctx, guild_id, opponent.clone()).await;
+
+    let invitation = format!(
+        "{} challenges you to {name}, putting ${wager} on the line.",
+        author_player.nick
+    );
+    if challenge(&ctx, &author_player, &opponent_player, &invitation).await? != Challenge::Accepted
+    {
+        return Ok(());
+    }
+
+    let Some(result) =
+        play_for_wager(&ctx, &mut game, [&author_player, &opponent_player], wager).await?
+    else {
+        return Ok(());
+    };
+
+    match result.winner() {
+        Some(0) => {
+            simple_channel_message(&ctx, &format!("{} Wins ${wager}!", author.mention())).await?;
+        }
+        Some(_) => {
+            simple_channel_message(&ctx, &format!("{} Wins ${wager}!", opponent.mention())).await?;
+        }
+        None => {
+            simple_channel_message(
+                &ctx,
+                &format!("{} and {} Tied :(", author.mention(), opponent.mention()),
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Plays against Johnson, who pays winners out of the house bankroll after taking his edge
+async fn play_against_house<G: Game>(
+    ctx: Context<'_>,
+    mut game: G,
+    wager: u32,
+    name: &str,
+    reason: LedgerReason,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("checked by caller");
+    let author = ctx.author();
+
+    let conf = match Database::new(ctx).get_server_conf(guild_id).await {
+        Ok(conf) => Some(conf),
+        Err(sqlx::Error::RowNotFound) => None,
+        Err(err) => return Err(err.into()),
+    };
+    let Some(payout) = house_terms(&ctx, conf.as_ref(), wager).await? else {
+        return Ok(());
+    };
+    let Some(escrow) = hold_house_wager(ctx, G::KEY, wager, G::WAGER_REASON).await? else {
+        return Ok(());
+    };
+
+    ctx.say(format!(
+        "{} challenges Johnson to {name}, putting ${wager} on the line. He pays ${payout} if he loses.",
+        author.mention()
+    ))
+    .await?;
+
+    let player = Player::new(&ctx, guild_id, author.clone()).await;
+    let result = match game.play(&ctx, [&player, &johnson(&ctx)]).await {
+        Ok(result) => result,
+        Err(err) => {
+            escrow.refund().await?;
+            return Err(err);
+        }
+    };
+
+    settle_with_house(&ctx, escrow, wager, payout, result, reason).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::gamble::session::{DEFAULT_HOUSE_EDGE, house_net, house_payout};
+    use rand::RngCore;
+
+    /// Hands out the given numbers in order, so a test decides how every flip and roll lands
+    struct Rigged(Vec<u64>);
+
+    impl RngCore for Rigged {
+        fn next_u32(&mut self) -> u32 {
+            (self.next_u64() >> 32) as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0.remove(0)
+        }
+
+        fn fill_bytes(&mut self, dst: &mut [u8]) {
+            rand::rand_core::impls::fill_bytes_via_next(self, dst)
+        }
+    }
+
+    fn rigged_coinflip(rigged: &[u64]) -> Coinflip<Rigged> {
+        Coinflip {
+            rng: Rigged(rigged.to_vec()),
+        }
+    }
+
+    fn rigged_dice(rigged: &[u64], sides: u32) -> DiceDuel<Rigged> {
+        DiceDuel {
+            rng: Rigged(rigged.to_vec()),
+            sides,
+        }
+    }
+
+    #[test]
+    fn heads_wins_for_the_challenger() {
+        assert_eq!(coinflip_result(Coin::Heads), GameResult::Winner(0));
+        assert_eq!(coinflip_result(Coin::Tails), GameResult::Winner(1));
+    }
+
+    #[test]
+    fn highest_roll_wins() {
+        assert_eq!(dice_result([5, 2]), GameResult::Winner(0));
+        assert_eq!(dice_result([1, 6]), GameResult::Winner(1));
+        assert_eq!(dice_result([4, 4]), GameResult::Draw);
+    }
+
+    #[test]
+    fn johnson_pays_out_on_heads() {
+        let (coin, result) = rigged_coinflip(&[0]).toss();
+
+        assert_eq!(coin, Coin::Heads);
+        assert_eq!(result, GameResult::Winner(0));
+        assert_eq!(
+            house_net(result, 100, house_payout(100, DEFAULT_HOUSE_EDGE)),
+            95
+        );
+    }
+
+    #[test]
+    fn johnson_takes_the_wager_on_tails() {
+        let (coin, result) = rigged_coinflip(&[u64::MAX]).toss();
+
+        assert_eq!(coin, Coin::Tails);
+        assert_eq!(result, GameResult::Winner(1));
+        assert_eq!(
+            house_net(result, 100, house_payout(100, DEFAULT_HOUSE_EDGE)),
+            -100
+        );
+    }
+
+    #[test]
+    fn dice_duel_pays_the_high_roller() {
+        let (rolls, result) = rigged_dice(&[u64::MAX, 0], 6).roll_both();
+
+        assert_eq!(rolls, [6, 1]);
+        assert_eq!(result, GameResult::Winner(0));
+        assert_eq!(house_net(result, 40, house_payout(40, 10)), 36);
+    }
+
+    #[test]
+    fn dice_duel_low_roller_loses_the_wager() {
+        let (rolls, result) = rigged_dice(&[0, u64::MAX], 20).roll_both();
+
+        assert_eq!(rolls, [1, 20]);
+        assert_eq!(result, GameResult::Winner(1));
+        assert_eq!(house_net(result, 40, house_payout(40, 10)), -40);
+    }
+
+    #[test]
+    fn dice_duel_draw_returns_the_wager() {
+        let (rolls, result) = rigged_dice(&[0, 0], 2).roll_both();
+
+        assert_eq!(rolls, [1, 1]);
+        assert_eq!(result, GameResult::Draw);
+        assert_eq!(house_net(result, 40, house_payout(40, 10)), 0);
+    }
+}
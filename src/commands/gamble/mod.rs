@@ -0,0 +1,244 @@
+use once_cell::sync::Lazy;
+use poise::CreateReply;
+use poise::serenity_prelude::{Color, CreateEmbed};
+use rand::Rng;
+use rand::distr::Distribution;
+use rand::distr::weighted::WeightedIndex;
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::{info, instrument, warn};
+
+use self::session::hold_house_wager;
+use crate::custom_types::command::{Context, Error};
+use crate::db::{Database, LedgerReason};
+use crate::utils::message::embed::base_embed;
+use crate::utils::message::send_simple_ephemeral;
+
+mod blackjack;
+mod duel;
+mod rps;
+mod session;
+
+pub use blackjack::blackjack;
+pub use duel::{coinflip, dice};
+pub use rps::{rock_paper_scissors, rps_tournament};
+pub use session::{DEFAULT_HOUSE_EDGE, MAX_HOUSE_EDGE};
+
+const SLOT_REELS: usize = 3;
+const SLOT_SPIN_DELAY: Duration = Duration::from_millis(750);
+const SLOT_SPINNING: &str = "🔄";
+
+static SLOT_CONFIG: Lazy<SlotConfig> = Lazy::new(|| {
+    serde_json::from_str(include_str!("../../../cfg/slots.json"))
+        .expect("embedded slots.json str should be valid json")
+});
+
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+enum SlotOptions {
+    SkullNBones,
+    Cherries,
+    Bells,
+    Diamonds,
+    Sevens,
+}
+
+impl SlotOptions {
+    fn emoji(&self) -> &'static str {
+        match self {
+            SlotOptions::SkullNBones => "☠️",
+            SlotOptions::Cherries => "🍒",
+            SlotOptions::Bells => "🔔",
+            SlotOptions::Diamonds => "💎",
+            SlotOptions::Sevens => "7️⃣",
+        }
+    }
+}
+
+/// A symbol on the reels, how often it lands and what it pays out. Payouts are multipliers of the
+/// bet, so a pair of 1 just gives the player their money back.
+#[derive(Deserialize, Debug)]
+struct SlotSymbol {
+    option: SlotOptions,
+    weight: u32,
+    pair: u64,
+    triple: u64,
+}
+
+/// The reel weights and payout table, loaded from cfg/slots.json
+#[derive(Deserialize, Debug)]
+struct SlotConfig {
+    symbols: Vec<SlotSymbol>,
+}
+
+#[poise::command(slash_command)]
+#[instrument(skip(ctx))]
+pub async fn slots(
+    ctx: Context<'_>,
+    #[description = "How many V-Bucks you're putting in"] bet: u32,
+) -> Result<(), Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    if bet == 0 {
+        send_simple_ephemeral(&ctx, "You have to bet something!").await?;
+        return Ok(());
+    }
+
+    let author = ctx.author();
+
+    // Johnson has to be able to cover a jackpot before he takes the bet
+    let bankroll = match Database::new(ctx).get_server_conf(guild_id).await {
+        Ok(conf) => conf.house_bankroll,
+        Err(sqlx::Error::RowNotFound) => 0,
+        Err(err) => return Err(err.into()),
+    };
+    let jackpot = i64::from(bet) * (top_multiplier() as i64 - 1);
+    if bankroll < jackpot {
+        send_simple_ephemeral(
+            &ctx,
+            &format!("Johnson can't cover that bet, he only has ${bankroll} in the bank!"),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let Some(escrow) = hold_house_wager(ctx, "slots", bet, LedgerReason::SlotsBet).await? else {
+        return Ok(());
+    };
+
+    let reels = ctx.data().rng.with(spin_reels);
+    let winnings = u64::from(bet) * slots_multiplier(&reels);
+    info!(reels = ?reels, winnings, "Spun slots");
+
+    // Settled before the reveal so a failed edit can't cost anyone their winnings
+    let net = winnings as i64 - i64::from(bet);
+    let settled = escrow
+        .settle_with_house(author, net, LedgerReason::SlotsWin)
+        .await?;
+
+    let mut shown: [Option<SlotOptions>; SLOT_REELS] = [None; SLOT_REELS];
+    let slots_message = ctx
+        .send(CreateReply::default().embed(slots_embed(&shown, bet)))
+        .await?;
+
+    // Reveal the reels one at a time
+    for (i, reel) in reels.iter().enumerate() {
+        tokio::time::sleep(SLOT_SPIN_DELAY).await;
+        shown[i] = Some(*reel);
+        if let Err(err) = slots_message
+            .edit(ctx, CreateReply::default().embed(slots_embed(&shown, bet)))
+            .await
+        {
+            warn!("Couldn't reveal slots reel {i}: {err}");
+        }
+    }
+
+    let result_embed = slots_embed(&shown, bet);
+    let result_embed = if settled.is_none() {
+        result_embed.field(
+            "Result",
+            format!("Johnson went broke before he could pay up, you get your ${bet} back!"),
+            false,
+        )
+    } else if winnings > 0 {
+        result_embed
+            .field("Result", format!("You won ${winnings}!"), false)
+            .color(Color::DARK_GREEN)
+    } else {
+        result_embed
+            .field("Result", "You lost :(", false)
+            .color(Color::RED)
+    };
+
+    slots_message
+        .edit(ctx, CreateReply::default().embed(result_embed))
+        .await?;
+
+    Ok(())
+}
+
+fn spin_reels(rng: &mut impl Rng) -> [SlotOptions; SLOT_REELS] {
+    let symbols = &SLOT_CONFIG.symbols;
+    let weighted_dist = WeightedIndex::new(symbols.iter().map(|s| s.weight))
+        .expect("slots.json should have at least one symbol with a weight");
+
+    std::array::from_fn(|_| symbols[weighted_dist.sample(rng)].option)
+}
+
+/// The most any spin can pay, as a multiplier of the bet
+fn top_multiplier() -> u64 {
+    SLOT_CONFIG
+        .symbols
+        .iter()
+        .map(|symbol| symbol.triple.max(symbol.pair))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Returns the best bet multiplier out of every symbol that landed more than once
+fn slots_multiplier(reels: &[SlotOptions]) -> u64 {
+    SLOT_CONFIG
+        .symbols
+        .iter()
+        .map(
+            |symbol| match reels.iter().filter(|r| **r == symbol.option).count() {
+                3 => symbol.triple,
+                2 => symbol.pair,
+                _ => 0,
+            },
+        )
+        .max()
+        .unwrap_or(0)
+}
+
+fn slots_embed(reels: &[Option<SlotOptions>], bet: u32) -> CreateEmbed {
+    let reel_str = reels
+        .iter()
+        .map(|r| r.map_or(SLOT_SPINNING, |r| r.emoji()))
+        .collect::<Vec<_>>()
+        .join(" | ");
+
+    base_embed()
+        .title("Slots")
+        .description(format!("# {reel_str}"))
+        .field("Bet", format!("${bet}"), false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use SlotOptions::*;
+
+    #[test]
+    fn triples_pay_the_triple() {
+        assert_eq!(slots_multiplier(&[Sevens, Sevens, Sevens]), 77);
+        assert_eq!(slots_multiplier(&[Cherries, Cherries, Cherries]), 5);
+    }
+
+    #[test]
+    fn pairs_pay_the_pair_wherever_they_land() {
+        assert_eq!(slots_multiplier(&[Bells, Bells, Cherries]), 2);
+        assert_eq!(slots_multiplier(&[Diamonds, Cherries, Diamonds]), 3);
+        assert_eq!(slots_multiplier(&[Cherries, Sevens, Sevens]), 5);
+    }
+
+    #[test]
+    fn no_match_pays_nothing() {
+        assert_eq!(slots_multiplier(&[Cherries, Bells, Sevens]), 0);
+    }
+
+    #[test]
+    fn skulls_never_pay() {
+        assert_eq!(
+            slots_multiplier(&[SkullNBones, SkullNBones, SkullNBones]),
+            0
+        );
+        assert_eq!(slots_multiplier(&[SkullNBones, SkullNBones, Bells]), 0);
+    }
+
+    #[test]
+    fn top_multiplier_is_the_best_triple() {
+        assert_eq!(top_multiplier(), 77);
+    }
+}
@@ -0,0 +1,755 @@
+use once_cell::sync::Lazy;
+use poise::serenity_prelude::futures::StreamExt;
+use poise::serenity_prelude::{
+    self, CreateActionRow, CreateButton, CreateEmbed, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, EditMessage, Mentionable, Timestamp,
+};
+use poise::{ChoiceParameter, CreateReply};
+use rand::Rng;
+use rand::distr::Distribution;
+use rand::distr::weighted::WeightedIndex;
+use rand::seq::{IndexedRandom, SliceRandom};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{debug, info, instrument};
+
+use super::session::{
+    Challenge, Escrow, Game, GameResult, Move, Player, challenge, collect_moves, hold_house_wager,
+    house_terms, johnson, play_for_wager, settle_with_house,
+};
+use crate::custom_types::command::{Context, Error};
+use crate::db::{Database, LedgerReason};
+use crate::utils::message::embed::base_embed;
+use crate::utils::message::{send_simple_ephemeral, simple_channel_message};
+
+// Ties after this many extra throws end a best-of match early
+const MAX_MATCH_TIES: u32 = 5;
+
+const TOURNAMENT_SIGNUP_TIME: Duration = Duration::from_secs(60);
+const MAX_TOURNAMENT_SIGNUP_TIME: Duration = Duration::from_secs(300);
+const MAX_TOURNAMENT_PLAYERS: usize = 16;
+
+/// The result of a move (outer key) against another move (inner key)
+static WIN_TABLE: Lazy<HashMap<Rps, HashMap<Rps, RpsResult>>> = Lazy::new(|| {
+    HashMap::from([
+        (
+            Rps::Rock,
+            HashMap::from([
+                (Rps::Rock, RpsResult::Tie),
+                (Rps::Paper, RpsResult::Loss),
+                (Rps::Scissors, RpsResult::Win),
+            ]),
+        ),
+        (
+            Rps::Paper,
+            HashMap::from([
+                (Rps::Rock, RpsResult::Win),
+                (Rps::Paper, RpsResult::Tie),
+                (Rps::Scissors, RpsResult::Loss),
+            ]),
+        ),
+        (
+            Rps::Scissors,
+            HashMap::from([
+                (Rps::Rock, RpsResult::Loss),
+                (Rps::Paper, RpsResult::Win),
+                (Rps::Scissors, RpsResult::Tie),
+            ]),
+        ),
+    ])
+});
+
+#[derive(Hash, PartialEq, Eq, Clone, Copy, Debug)]
+enum Rps {
+    Rock,
+    Paper,
+    Scissors,
+}
+
+impl Move for Rps {
+    const ALL: &'static [Rps] = &[Rps::Rock, Rps::Paper, Rps::Scissors];
+
+    fn id(&self) -> &'static str {
+        match self {
+            Rps::Rock => "rock",
+            Rps::Paper => "paper",
+            Rps::Scissors => "scissors",
+        }
+    }
+}
+
+impl Rps {
+    /// The move that beats this one
+    fn counter(&self) -> Rps {
+        match self {
+            Rps::Rock => Rps::Paper,
+            Rps::Paper => Rps::Scissors,
+            Rps::Scissors => Rps::Rock,
+        }
+    }
+}
+
+impl FromStr for Rps {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Rps, Self::Err> {
+        match input {
+            "Rock" | "rock" => Ok(Rps::Rock),
+            "Paper" | "paper" => Ok(Rps::Paper),
+            "Scissors" | "scissors" => Ok(Rps::Scissors),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The Result of RPS from the perspective of the command invoker
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum RpsResult {
+    Win,
+    Loss,
+    Tie,
+}
+
+/// How many throws someone has to win to take an RPS match
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum RpsRounds {
+    #[name = "Single throw"]
+    One,
+    #[name = "Best of 3"]
+    BestOf3,
+    #[name = "Best of 5"]
+    BestOf5,
+}
+
+impl RpsRounds {
+    fn wins_needed(&self) -> u32 {
+        match self {
+            RpsRounds::One => 1,
+            RpsRounds::BestOf3 => 2,
+            RpsRounds::BestOf5 => 3,
+        }
+    }
+}
+
+/// Johnson's side of a game against him
+#[derive(Debug)]
+struct House {
+    /// How often the member has thrown each move, only kept when Johnson adapts to them
+    history: Option<HashMap<Rps, i64>>,
+    /// Every move the member has thrown this game
+    thrown: Vec<Rps>,
+}
+
+impl House {
+    fn pick(&self, rng: &mut impl Rng) -> Rps {
+        match &self.history {
+            None => *Rps::ALL.choose(rng).expect("there are always moves"),
+            Some(history) => {
+                // Guess their move based on how often they've thrown it and beat it. Every move
+                // starts with a count of 1 so new players aren't completely predictable.
+                let weights = Rps::ALL
+                    .iter()
+                    .map(|m| history.get(m).copied().unwrap_or(0).max(0) + 1);
+                let guess = WeightedIndex::new(weights)
+                    .expect("weights are always positive")
+                    .sample(rng);
+
+                Rps::ALL[guess].counter()
+            }
+        }
+    }
+
+    fn observe(&mut self, choice: Rps) {
+        self.thrown.push(choice);
+        if let Some(history) = &mut self.history {
+            *history.entry(choice).or_default() += 1;
+        }
+    }
+}
+
+fn rps_result(choice: Rps, other: Rps) -> RpsResult {
+    *WIN_TABLE
+        .get(&choice)
+        .and_then(|results| results.get(&other))
+        .expect("every pair of moves should be in the win table")
+}
+
+/// A match of RPS, played until someone wins enough throws
+#[derive(Debug)]
+struct RpsMatch {
+    wins_needed: u32,
+    max_ties: u32,
+    /// Set when playing Johnson, who is always the second player
+    house: Option<House>,
+}
+
+impl RpsMatch {
+    fn new(rounds: RpsRounds, max_ties: u32) -> RpsMatch {
+        RpsMatch {
+            wins_needed: rounds.wins_needed(),
+            max_ties,
+            house: None,
+        }
+    }
+}
+
+impl Game for RpsMatch {
+    const KEY: &'static str = "rps";
+    const WAGER_REASON: LedgerReason = LedgerReason::RpsWager;
+    const WIN_REASON: LedgerReason = LedgerReason::RpsWin;
+
+    /// Plays throws until someone wins enough of them. Ties don't count towards anyone's score;
+    /// once there have been more than max_ties of them the match ends with whoever is ahead
+    /// winning, or a draw if nobody is. When playing the house, Johnson picks his move before
+    /// each throw.
+    async fn play(
+        &mut self,
+        ctx: &Context<'_>,
+        players: [&Player; 2],
+    ) -> Result<GameResult, Error> {
+        let mut scores = [0, 0];
+        let mut ties = 0;
+        let mut throws = Vec::new();
+
+        let mut score_message = ctx
+            .channel_id()
+            .send_message(
+                ctx,
+                CreateMessage::new().embed(score_embed(
+                    players,
+                    &scores,
+                    &throws,
+                    self.wins_needed,
+                )),
+            )
+            .await?;
+
+        loop {
+            let preset = [
+                None,
+                self.house
+                    .as_ref()
+                    .map(|h| ctx.data().rng.with(|rng| h.pick(rng))),
+            ];
+            let choices = match collect_moves(
+                ctx,
+                &mut score_message,
+                score_embed(players, &scores, &throws, self.wins_needed),
+                players,
+                preset,
+            )
+            .await?
+            {
+                [Some(first), Some(second)] => [first, second],
+                [None, Some(_)] => return Ok(GameResult::Forfeit { player: 0 }),
+                [Some(_), None] => return Ok(GameResult::Forfeit { player: 1 }),
+                [None, None] => return Ok(GameResult::Abandoned),
+            };
+
+            info!("Players chose {:?}", choices);
+            if let Some(house) = &mut self.house {
+                house.observe(choices[0]);
+            }
+
+            match rps_result(choices[0], choices[1]) {
+                RpsResult::Win => scores[0] += 1,
+                RpsResult::Loss => scores[1] += 1,
+                RpsResult::Tie => ties += 1,
+            }
+            throws.push(format!(
+                "{} chose {:?}, while {} chose {:?}!",
+                players[0].nick, choices[0], players[1].nick, choices[1]
+            ));
+
+            score_message
+                .edit(
+                    ctx,
+                    EditMessage::new().embed(score_embed(
+                        players,
+                        &scores,
+                        &throws,
+                        self.wins_needed,
+                    )),
+                )
+                .await?;
+
+            if scores[0] >= self.wins_needed {
+                return Ok(GameResult::Winner(0));
+            } else if scores[1] >= self.wins_needed {
+                return Ok(GameResult::Winner(1));
+            } else if ties > self.max_ties {
+                return Ok(match scores[0].cmp(&scores[1]) {
+                    Ordering::Greater => GameResult::Winner(0),
+                    Ordering::Less => GameResult::Winner(1),
+                    Ordering::Equal => GameResult::Draw,
+                });
+            }
+        }
+    }
+}
+
+#[poise::command(slash_command)]
+#[instrument(skip(ctx))]
+pub async fn rock_paper_scissors(
+    ctx: Context<'_>,
+    #[description = "Who you're challenging"] opponent: serenity_prelude::User,
+    #[description = "What you're willing to wager"] wager: u32,
+    #[description = "How many throws it takes to win. By default this is a single throw."]
+    rounds: Option<RpsRounds>,
+) -> Result<(), Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(());
+    };
+
+    let rounds = rounds.unwrap_or(RpsRounds::One);
+    if opponent.id == ctx.framework().bot_id {
+        return play_against_house(ctx, wager, rounds).await;
+    }
+
+    if opponent.bot {
+        ctx.say("You can't play with a bot! (they dont have hands)")
+            .await?;
+        return Ok(());
+    }
+
+    let driver = Database::new(ctx);
+    let author = ctx.author();
+
+    debug!("Checking for author money");
+    if driver.get_user(guild_id, author).await?.vbucks < wager.into() {
+        send_simple_ephemeral(&ctx, "You do not have enough money for this wager!").await?;
+        return Ok(());
+    }
+
+    debug!("Checking for opponent money");
+    if driver.get_user(guild_id, &opponent).await?.vbucks < wager.into() {
+        send_simple_ephemeral(
+            &ctx,
+            &format!("{opponent} does not have enough money for this wager!"),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    ctx.defer_ephemeral().await?;
+
+    let author_player = Player::new(&ctx, guild_id, author.clone()).await;
+    let opponent_player = Player::new(&ctx, guild_id, opponent.clone()).await;
+
+    let invitation = format!(
+        "{} challenges you to a Rock Paper Scissors Duel ({}), putting ${wager} on the line.",
+        author_player.nick,
+        rounds.name()
+    );
+    if challenge(&ctx, &author_player, &opponent_player, &invitation).await? != Challenge::Accepted
+    {
+        return Ok(());
+    }
+
+    // A single throw can end in a tie, longer matches replay them
+    let max_ties = match rounds {
+        RpsRounds::One => 0,
+        _ => MAX_MATCH_TIES,
+    };
+    let mut game = RpsMatch::new(rounds, max_ties);
+
+    let Some(result) =
+        play_for_wager(&ctx, &mut game, [&author_player, &opponent_player], wager).await?
+    else {
+        return Ok(());
+    };
+
+    match result.winner() {
+        // You Win!
+        Some(0) => {
+            simple_channel_message(&ctx, &format!("{} Wins!", author.mention())).await?;
+        }
+        // You lose :((
+        Some(_) => {
+            simple_channel_message(&ctx, &format!("{} Wins! :((", opponent.mention())).await?;
+        }
+        None if result == GameResult::Abandoned => {
+            ctx.reply("Timeout! Neither of you picked a move.").await?;
+        }
+        // No one wins :(
+        None => {
+            simple_channel_message(
+                &ctx,
+                &format!("{} and {} Tied :(", author.mention(), opponent.mention()),
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Plays against Johnson, who pays winners out of the house bankroll after taking his edge
+async fn play_against_house(ctx: Context<'_>, wager: u32, rounds: RpsRounds) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("checked by caller");
+    let driver = Database::new(ctx);
+    let author = ctx.author();
+
+    let conf = match driver.get_server_conf(guild_id).await {
+        Ok(conf) => Some(conf),
+        Err(sqlx::Error::RowNotFound) => None,
+        Err(err) => return Err(err.into()),
+    };
+    let Some(payout) = house_terms(&ctx, conf.as_ref(), wager).await? else {
+        return Ok(());
+    };
+
+    let history = if conf.as_ref().is_some_and(|c| c.rps_adaptive) {
+        Some(
+            driver
+                .get_rps_moves(guild_id, author)
+                .await?
+                .into_iter()
+                .filter_map(|(m, count)| Some((Rps::from_str(&m).ok()?, count)))
+                .collect(),
+        )
+    } else {
+        None
+    };
+
+    let Some(escrow) = hold_house_wager(ctx, RpsMatch::KEY, wager, RpsMatch::WAGER_REASON).await?
+    else {
+        return Ok(());
+    };
+
+    ctx.say(format!(
+        "{} challenges Johnson to a Rock Paper Scissors Duel ({}), putting ${wager} on the line. He pays ${payout} if he loses.",
+        author.mention(),
+        rounds.name()
+    ))
+    .await?;
+
+    let player = Player::new(&ctx, guild_id, author.clone()).await;
+    let max_ties = match rounds {
+        RpsRounds::One => 0,
+        _ => MAX_MATCH_TIES,
+    };
+
+    let mut game = RpsMatch {
+        house: Some(House {
+            history,
+            thrown: Vec::new(),
+        }),
+        ..RpsMatch::new(rounds, max_ties)
+    };
+    let result = match game.play(&ctx, [&player, &johnson(&ctx)]).await {
+        Ok(result) => result,
+        Err(err) => {
+            escrow.refund().await?;
+            return Err(err);
+        }
+    };
+
+    settle_with_house(&ctx, escrow, wager, payout, result, LedgerReason::RpsHouse).await?;
+
+    if let Some(house) = &game.house {
+        let thrown: Vec<&str> = house.thrown.iter().map(Rps::id).collect();
+        driver.record_rps_moves(guild_id, author, &thrown).await?;
+    }
+
+    Ok(())
+}
+
+/// Start a Rock Paper Scissors tournament, the last one standing takes every entry fee
+#[poise::command(slash_command, guild_only)]
+#[instrument(skip(ctx))]
+pub async fn rps_tournament(
+    ctx: Context<'_>,
+    #[description = "What everyone pays to join"] entry_fee: u32,
+    #[description = "How many throws it takes to win a match. By default this is a single throw."]
+    rounds: Option<RpsRounds>,
+    #[description = "Seconds people have to join. By default this is 60."] signup_time: Option<u64>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let driver = Database::new(ctx);
+    let rounds = rounds.unwrap_or(RpsRounds::One);
+    let signup_time = Duration::from_secs(
+        signup_time
+            .unwrap_or(TOURNAMENT_SIGNUP_TIME.as_secs())
+            .clamp(10, MAX_TOURNAMENT_SIGNUP_TIME.as_secs()),
+    );
+
+    let host = ctx.author();
+    if driver.get_user(guild_id, host).await?.vbucks < entry_fee.into() {
+        send_simple_ephemeral(&ctx, "You can't afford your own tournament!").await?;
+        return Ok(());
+    }
+
+    let mut players = vec![Player::new(&ctx, guild_id, host.clone()).await];
+    let signup_ends = Timestamp::now().unix_timestamp() + signup_time.as_secs() as i64;
+
+    let reply = ctx
+        .send(
+            CreateReply::default()
+                .embed(signup_embed(&players, entry_fee, rounds, signup_ends))
+                .components(vec![CreateActionRow::Buttons(vec![
+                    CreateButton::new("join").label("Join"),
+                ])]),
+        )
+        .await?;
+    let message = reply.message().await?.into_owned();
+
+    let mut joins = message
+        .await_component_interactions(ctx)
+        .timeout(signup_time)
+        .stream();
+
+    while let Some(interaction) = joins.next().await {
+        let user = &interaction.user;
+
+        let rejection = if players.iter().any(|p| p.user.id == user.id) {
+            Some("You're already in!")
+        } else if players.len() >= MAX_TOURNAMENT_PLAYERS {
+            Some("This tournament is full!")
+        } else if driver.get_user(guild_id, user).await?.vbucks < entry_fee.into() {
+            Some("You can't afford the entry fee!")
+        } else {
+            None
+        };
+
+        if let Some(rejection) = rejection {
+            let _ = interaction
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new()
+                            .content(rejection)
+                            .ephemeral(true),
+                    ),
+                )
+                .await;
+            continue;
+        }
+
+        info!("{} joined the RPS tournament", user.name);
+        players.push(Player::new(&ctx, guild_id, user.clone()).await);
+
+        interaction
+            .create_response(
+                ctx,
+                CreateInteractionResponse::UpdateMessage(
+                    CreateInteractionResponseMessage::new().embed(signup_embed(
+                        &players,
+                        entry_fee,
+                        rounds,
+                        signup_ends,
+                    )),
+                ),
+            )
+            .await?;
+    }
+
+    reply
+        .edit(
+            ctx,
+            CreateReply::default()
+                .embed(signup_embed(&players, entry_fee, rounds, signup_ends))
+                .components(Vec::new()),
+        )
+        .await?;
+
+    // Hold everyone's entry fee, leaving out anyone that spent their money during signups
+    let escrow = Escrow::open(ctx, guild_id, "rps_tournament").await?;
+    let mut paid_players = Vec::with_capacity(players.len());
+    for player in players {
+        let held = match escrow
+            .hold(&player.user, entry_fee, LedgerReason::RpsTournamentEntry)
+            .await
+        {
+            Ok(held) => held,
+            Err(err) => {
+                escrow.refund().await?;
+                return Err(err);
+            }
+        };
+
+        if held {
+            paid_players.push(player);
+        } else {
+            simple_channel_message(
+                &ctx,
+                &format!(
+                    "{} can't afford the entry fee anymore and is out!",
+                    player.user.mention()
+                ),
+            )
+            .await?;
+        }
+    }
+
+    if paid_players.len() < 2 {
+        // Nobody to play against, give back what was taken
+        escrow.refund().await?;
+        simple_channel_message(&ctx, "Not enough people joined the tournament :(").await?;
+        return Ok(());
+    }
+
+    info!(players = paid_players.len(), "Starting RPS tournament");
+
+    let champion = match play_bracket(&ctx, paid_players, rounds).await {
+        Ok(champion) => champion,
+        Err(err) => {
+            escrow.refund().await?;
+            return Err(err);
+        }
+    };
+
+    let pot = escrow
+        .release(&champion.user, LedgerReason::RpsTournamentWin)
+        .await?;
+
+    info!(pot, "{} won the RPS tournament", champion.user.name);
+    simple_channel_message(
+        &ctx,
+        &format!(
+            "{} won the tournament and takes home ${pot}!",
+            champion.user.mention()
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Plays rounds of matches between the remaining players until only the champion is left
+async fn play_bracket(
+    ctx: &Context<'_>,
+    players: Vec<Player>,
+    rounds: RpsRounds,
+) -> Result<Player, Error> {
+    let mut remaining = players;
+    ctx.data().rng.with(|rng| remaining.shuffle(rng));
+    let mut round = 1;
+
+    while remaining.len() > 1 {
+        ctx.channel_id()
+            .send_message(
+                ctx,
+                CreateMessage::new().embed(bracket_embed(&remaining, round)),
+            )
+            .await?;
+
+        let mut advancing = Vec::with_capacity(remaining.len().div_ceil(2));
+        let mut entrants = remaining.into_iter();
+
+        while let Some(first) = entrants.next() {
+            let Some(second) = entrants.next() else {
+                // Odd one out gets a bye
+                advancing.push(first);
+                break;
+            };
+
+            let result = RpsMatch::new(rounds, MAX_MATCH_TIES)
+                .play(ctx, [&first, &second])
+                .await?;
+
+            // Still tied after all those throws, let fate decide
+            let first_wins = result.winner().map_or_else(
+                || ctx.data().rng.with(|rng| rng.random_bool(0.5)),
+                |winner| winner == 0,
+            );
+
+            let (winner, loser) = if first_wins {
+                (first, second)
+            } else {
+                (second, first)
+            };
+
+            let forfeit = if matches!(result, GameResult::Forfeit { .. }) {
+                " by forfeit"
+            } else {
+                ""
+            };
+            simple_channel_message(
+                ctx,
+                &format!(
+                    "{} beat {}{forfeit}!",
+                    winner.user.mention(),
+                    loser.user.mention()
+                ),
+            )
+            .await?;
+
+            advancing.push(winner);
+        }
+
+        remaining = advancing;
+        round += 1;
+    }
+
+    Ok(remaining
+        .pop()
+        .expect("a tournament always ends with a winner"))
+}
+
+fn signup_embed(
+    players: &[Player],
+    entry_fee: u32,
+    rounds: RpsRounds,
+    signup_ends: i64,
+) -> CreateEmbed {
+    let names = players
+        .iter()
+        .map(|p| p.user.mention().to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    base_embed()
+        .title("Rock Paper Scissors Tournament")
+        .description(format!("Signups close <t:{signup_ends}:R>"))
+        .field("Entry Fee", format!("${entry_fee}"), true)
+        .field("Matches", rounds.name(), true)
+        .field(
+            "Pot",
+            format!("${}", i64::from(entry_fee) * players.len() as i64),
+            true,
+        )
+        .field(format!("Players ({})", players.len()), names, false)
+}
+
+fn bracket_embed(players: &[Player], round: u32) -> CreateEmbed {
+    let matches = players
+        .chunks(2)
+        .map(|pair| match pair {
+            [first, second] => format!("{} vs {}", first.user.mention(), second.user.mention()),
+            [bye] => format!("{} gets a bye", bye.user.mention()),
+            _ => unreachable!("chunks of 2 are never empty"),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    base_embed()
+        .title(format!("Tournament Round {round}"))
+        .description(matches)
+}
+
+fn score_embed(
+    players: [&Player; 2],
+    scores: &[u32; 2],
+    throws: &[String],
+    wins_needed: u32,
+) -> CreateEmbed {
+    base_embed()
+        .title(format!("{} vs {}", players[0].nick, players[1].nick))
+        .description(if throws.is_empty() {
+            String::from("Waiting for the first throw...")
+        } else {
+            throws.join("\n")
+        })
+        .field(
+            "Score",
+            format!(
+                "{} {} - {} {}",
+                players[0].nick, scores[0], scores[1], players[1].nick
+            ),
+            false,
+        )
+        .field("First To", wins_needed.to_string(), false)
+}
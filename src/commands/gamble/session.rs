@@ -0,0 +1,561 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::time::Duration;
+
+use poise::serenity_prelude::futures::StreamExt;
+use poise::serenity_prelude::{
+    ComponentInteractionCollector, CreateActionRow, CreateButton, CreateEmbed,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage, EditMessage,
+    GuildId, Mentionable, Message, User, UserId,
+};
+use rand::Rng;
+use tracing::{info, instrument};
+
+use crate::custom_types::command::{Context, Error};
+use crate::custom_types::mongo_schema::ServerConfig;
+use crate::db::{Database, LedgerReason, TransferError};
+use crate::utils::message::interaction::wait_for_user_interaction;
+use crate::utils::message::{send_simple_ephemeral, simple_channel_message};
+
+/// Johnson's cut of winnings against the house, in percent, until a server sets its own
+pub const DEFAULT_HOUSE_EDGE: i64 = 5;
+pub const MAX_HOUSE_EDGE: i64 = 50;
+
+// How long someone has to accept a challenge
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(60);
+// How long players get to make their move each turn
+pub(super) const TURN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A game two members can play against each other for V-Bucks. Everything around the game itself
+/// (challenging, holding wagers and paying out) is handled by play_for_wager.
+pub trait Game: Send {
+    /// Tells this game's escrows apart from other games'
+    const KEY: &'static str;
+    /// What the ledger calls wagers going into escrow
+    const WAGER_REASON: LedgerReason;
+    /// What the ledger calls the pot going to the winner
+    const WIN_REASON: LedgerReason;
+
+    /// Plays the game out between two players, see collect_moves for taking turns
+    fn play(
+        &mut self,
+        ctx: &Context<'_>,
+        players: [&Player; 2],
+    ) -> impl Future<Output = Result<GameResult, Error>> + Send;
+}
+
+/// Something a player can pick on their turn
+pub trait Move: Copy + Debug + Send + Sync + 'static {
+    /// Every move, in the order their buttons are shown
+    const ALL: &'static [Self];
+
+    /// Unique among a game's moves, used in button ids
+    fn id(&self) -> &'static str;
+
+    fn label(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+/// Someone playing a game along with the name to call them by
+#[derive(Debug)]
+pub struct Player {
+    pub user: User,
+    pub nick: String,
+}
+
+impl Player {
+    pub async fn new(ctx: &Context<'_>, guild_id: GuildId, user: User) -> Player {
+        let nick = user
+            .nick_in(ctx, guild_id)
+            .await
+            .unwrap_or(user.name.clone());
+
+        Player { user, nick }
+    }
+}
+
+/// How a game between two players ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    /// The winner, by their index
+    Winner(usize),
+    Draw,
+    /// A player didn't take their turn in time and gave up the game, by their index
+    Forfeit {
+        player: usize,
+    },
+    /// Neither player took their turn in time
+    Abandoned,
+}
+
+impl GameResult {
+    /// Who takes the pot, if anyone
+    pub fn winner(&self) -> Option<usize> {
+        match self {
+            GameResult::Winner(player) => Some(*player),
+            GameResult::Forfeit { player } => Some(1 - player),
+            GameResult::Draw | GameResult::Abandoned => None,
+        }
+    }
+}
+
+/// What someone said to a challenge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Challenge {
+    Accepted,
+    Declined,
+    TimedOut,
+}
+
+/// Asks the opponent whether they want to play and lets the challenger know what they said. The
+/// invitation is sent after a mention of the opponent.
+#[instrument(skip_all, fields(opponent = opponent.user.name))]
+pub async fn challenge(
+    ctx: &Context<'_>,
+    challenger: &Player,
+    opponent: &Player,
+    invitation: &str,
+) -> Result<Challenge, Error> {
+    let message = ctx
+        .channel_id()
+        .send_message(
+            ctx,
+            CreateMessage::new()
+                .content(format!(
+                    "{}, {invitation} Do you accept?",
+                    opponent.user.mention()
+                ))
+                .button(CreateButton::new("accept").label("Accept"))
+                .button(CreateButton::new("decline").label("Decline")),
+        )
+        .await?;
+
+    info!("Waiting for interaction from acceptance message");
+    let interaction =
+        wait_for_user_interaction(ctx, &message, opponent.user.id, CHALLENGE_TIMEOUT).await;
+    let response = match &interaction {
+        Some(interaction) if interaction.data.custom_id == "accept" => Challenge::Accepted,
+        Some(_) => Challenge::Declined,
+        None => Challenge::TimedOut,
+    };
+
+    // Answer the button press before the message goes away, otherwise it shows up as failed
+    if let Some(interaction) = interaction {
+        let _ = interaction
+            .create_response(ctx, CreateInteractionResponse::Acknowledge)
+            .await;
+    }
+
+    let _ = message.delete(ctx).await;
+
+    info!(response = ?response, "Got challenge response");
+    let challenger = challenger.user.mention();
+    let opponent = &opponent.nick;
+    simple_channel_message(
+        ctx,
+        &match response {
+            Challenge::Accepted => format!("{opponent} has accepted the invitation {challenger}!"),
+            Challenge::Declined => format!("{opponent} has declined the invitation {challenger}!"),
+            Challenge::TimedOut => format!("{opponent} did not reply in time, {challenger}!"),
+        },
+    )
+    .await?;
+
+    Ok(response)
+}
+
+/// V-Bucks held for a game until it's over, so nobody can spend their stake mid-game. Anything
+/// still held when Johnson restarts is refunded.
+pub struct Escrow<'a> {
+    db: Database<'a>,
+    guild_id: GuildId,
+    id: i64,
+    // Johnson, for settling games against the house
+    house: UserId,
+}
+
+impl<'a> Escrow<'a> {
+    pub async fn open(
+        ctx: Context<'a>,
+        guild_id: GuildId,
+        game: &str,
+    ) -> Result<Escrow<'a>, Error> {
+        let db = Database::new(ctx);
+        let id = db.open_escrow(guild_id, game).await?;
+
+        Ok(Escrow {
+            db,
+            guild_id,
+            id,
+            house: ctx.framework().bot_id,
+        })
+    }
+
+    /// Takes a player's stake. Returns false without taking anything if they can't afford it.
+    pub async fn hold(
+        &self,
+        user: &User,
+        amount: u32,
+        reason: LedgerReason,
+    ) -> Result<bool, Error> {
+        match self
+            .db
+            .hold_in_escrow(self.id, self.guild_id, user, amount.into(), reason)
+            .await
+        {
+            Ok(()) => Ok(true),
+            Err(TransferError::InsufficientFunds { .. }) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Pays everything held to the winner, returning how much they got
+    pub async fn release(self, winner: &User, reason: LedgerReason) -> Result<i64, Error> {
+        Ok(self.db.release_escrow(self.id, winner, reason).await?)
+    }
+
+    /// Settles a game against Johnson, moving net between the player's stake and the house
+    /// bankroll. Returns how much went back to them, or None after refunding everyone if the
+    /// bankroll can't cover their winnings anymore.
+    pub async fn settle_with_house(
+        self,
+        user: &User,
+        net: i64,
+        reason: LedgerReason,
+    ) -> Result<Option<i64>, Error> {
+        match self
+            .db
+            .settle_house_escrow(self.id, user, self.house, net, reason)
+            .await
+        {
+            Ok(returned) => Ok(Some(returned)),
+            Err(TransferError::HouseCantCover { .. }) => {
+                self.refund().await?;
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Gives everyone back what they put in
+    pub async fn refund(self) -> Result<(), Error> {
+        Ok(self.db.refund_escrow(self.id).await?)
+    }
+}
+
+/// Holds both players' wagers, plays the game and pays the winner. Everyone is refunded if
+/// nobody wins or something goes wrong mid-game. Returns None without playing if someone can't
+/// cover the wager anymore.
+pub async fn play_for_wager<G: Game>(
+    ctx: &Context<'_>,
+    game: &mut G,
+    players: [&Player; 2],
+    wager: u32,
+) -> Result<Option<GameResult>, Error> {
+    let guild_id = ctx.guild_id().expect("games are only played in guilds");
+    let escrow = Escrow::open(*ctx, guild_id, G::KEY).await?;
+
+    for player in players {
+        let held = match escrow.hold(&player.user, wager, G::WAGER_REASON).await {
+            Ok(held) => held,
+            Err(err) => {
+                escrow.refund().await?;
+                return Err(err);
+            }
+        };
+
+        if !held {
+            escrow.refund().await?;
+            simple_channel_message(
+                ctx,
+                &format!(
+                    "{} doesn't have enough V-Bucks for this wager anymore!",
+                    player.user.mention()
+                ),
+            )
+            .await?;
+            return Ok(None);
+        }
+    }
+
+    let result = match game.play(ctx, players).await {
+        Ok(result) => result,
+        Err(err) => {
+            escrow.refund().await?;
+            return Err(err);
+        }
+    };
+
+    match result.winner() {
+        Some(winner) => {
+            escrow.release(&players[winner].user, G::WIN_REASON).await?;
+        }
+        None => escrow.refund().await?,
+    }
+
+    info!(result = ?result, game = G::KEY, "Settled game");
+
+    Ok(Some(result))
+}
+
+/// Johnson, for games played against the house. He's always the second player.
+pub fn johnson(ctx: &Context<'_>) -> Player {
+    Player {
+        user: User::clone(&ctx.cache().current_user()),
+        nick: String::from("Johnson"),
+    }
+}
+
+/// What Johnson pays out for a win after taking his cut
+pub fn house_payout(wager: u32, edge: i64) -> i64 {
+    i64::from(wager) * (100 - edge) / 100
+}
+
+/// What the author makes from a game against Johnson, who always plays second. Nobody winning
+/// gives the wager back.
+pub fn house_net(result: GameResult, wager: u32, payout: i64) -> i64 {
+    match result.winner() {
+        Some(0) => payout,
+        Some(_) => -i64::from(wager),
+        None => 0,
+    }
+}
+
+/// Works out what Johnson pays if the author wins a wager against him. Returns None after
+/// telling them why if his bankroll can't cover the payout.
+pub async fn house_terms(
+    ctx: &Context<'_>,
+    conf: Option<&ServerConfig>,
+    wager: u32,
+) -> Result<Option<i64>, Error> {
+    let edge = conf
+        .and_then(|c| c.house_edge)
+        .unwrap_or(DEFAULT_HOUSE_EDGE);
+    let bankroll = conf.map_or(0, |c| c.house_bankroll);
+    let payout = house_payout(wager, edge);
+
+    if bankroll < payout {
+        send_simple_ephemeral(
+            ctx,
+            &format!("Johnson can't cover that bet, he only has ${bankroll} in the bank!"),
+        )
+        .await?;
+        return Ok(None);
+    }
+
+    Ok(Some(payout))
+}
+
+/// Holds the author's wager for a game against Johnson, so they can't spend it before the game
+/// is settled. Returns None after telling them if they can't afford it.
+pub async fn hold_house_wager<'a>(
+    ctx: Context<'a>,
+    game: &str,
+    wager: u32,
+    reason: LedgerReason,
+) -> Result<Option<Escrow<'a>>, Error> {
+    let guild_id = ctx.guild_id().expect("games are only played in guilds");
+    // Makes sure they have a row to hold the wager from
+    Database::new(ctx).get_user(guild_id, ctx.author()).await?;
+
+    let escrow = Escrow::open(ctx, guild_id, game).await?;
+    match escrow.hold(ctx.author(), wager, reason).await {
+        Ok(true) => Ok(Some(escrow)),
+        Ok(false) => {
+            escrow.refund().await?;
+            send_simple_ephemeral(&ctx, "You do not have enough money for this wager!").await?;
+            Ok(None)
+        }
+        Err(err) => {
+            escrow.refund().await?;
+            Err(err)
+        }
+    }
+}
+
+/// Settles the author's held wager with the house bankroll once a game against Johnson is over
+/// and announces the result. Draws give the wager back.
+pub async fn settle_with_house(
+    ctx: &Context<'_>,
+    escrow: Escrow<'_>,
+    wager: u32,
+    payout: i64,
+    result: GameResult,
+    reason: LedgerReason,
+) -> Result<(), Error> {
+    let author = ctx.author();
+
+    let net = house_net(result, wager, payout);
+
+    if escrow
+        .settle_with_house(author, net, reason)
+        .await?
+        .is_none()
+    {
+        simple_channel_message(
+            ctx,
+            &format!(
+                "Johnson went broke before he could pay up, {} gets their ${wager} back!",
+                author.mention()
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    info!(net, %reason, "Settled game against Johnson");
+    match result.winner() {
+        Some(0) => {
+            simple_channel_message(ctx, &format!("{} Wins ${net}!", author.mention())).await?;
+        }
+        Some(_) => {
+            simple_channel_message(
+                ctx,
+                &format!("Johnson Wins! {} loses ${wager}", author.mention()),
+            )
+            .await?;
+        }
+        None => {
+            simple_channel_message(ctx, &format!("{} and Johnson Tied :(", author.mention()))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Has both players pick a move at the same time through ephemeral prompts, keeping the status
+/// message up to date with who has locked in. Moves that are already known, like Johnson's, are
+/// passed in as preset. Anyone that doesn't pick in time gets None.
+pub async fn collect_moves<M: Move>(
+    ctx: &Context<'_>,
+    status: &mut Message,
+    status_embed: CreateEmbed,
+    players: [&Player; 2],
+    preset: [Option<M>; 2],
+) -> Result<[Option<M>; 2], Error> {
+    // Every turn gets its own ids so stray clicks on old prompts are ignored
+    let prefix = format!("move-{}", ctx.data().rng.with(|rng| rng.random::<u64>()));
+    let pick_id = format!("{prefix}:pick");
+    let mut moves = preset;
+
+    status
+        .edit(
+            ctx,
+            EditMessage::new()
+                .embed(
+                    status_embed
+                        .clone()
+                        .field("Locked In", locked_in(players, &moves), false),
+                )
+                .components(vec![CreateActionRow::Buttons(vec![
+                    CreateButton::new(&pick_id).label("Choose your move"),
+                ])]),
+        )
+        .await?;
+
+    let filter_prefix = prefix.clone();
+    let mut interactions = ComponentInteractionCollector::new(ctx)
+        .filter(move |i| i.data.custom_id.starts_with(&filter_prefix))
+        .timeout(TURN_TIMEOUT)
+        .stream();
+
+    while let Some(interaction) = interactions.next().await {
+        let Some(i) = players
+            .iter()
+            .position(|p| p.user.id == interaction.user.id)
+        else {
+            info!(
+                "{} attempted interaction with move prompt",
+                interaction.user.id
+            );
+            let _ = interaction
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new()
+                            .content("This is not meant for you!")
+                            .ephemeral(true),
+                    ),
+                )
+                .await;
+            continue;
+        };
+
+        let picked = interaction
+            .data
+            .custom_id
+            .strip_prefix(&prefix)
+            .and_then(|id| id.strip_prefix(':'))
+            .and_then(|id| M::ALL.iter().find(|m| m.id() == id));
+
+        let response = match (moves[i], picked) {
+            (Some(_), _) => CreateInteractionResponseMessage::new()
+                .content("You've already locked in!")
+                .ephemeral(true),
+            (None, None) => CreateInteractionResponseMessage::new()
+                .content("Choose a move!")
+                .ephemeral(true)
+                .components(vec![CreateActionRow::Buttons(
+                    M::ALL
+                        .iter()
+                        .map(|m| CreateButton::new(format!("{prefix}:{}", m.id())).label(m.label()))
+                        .collect(),
+                )]),
+            (None, Some(picked)) => {
+                info!("{} locked in their move", interaction.user.name);
+                moves[i] = Some(*picked);
+
+                let _ = interaction
+                    .create_response(
+                        ctx,
+                        CreateInteractionResponse::UpdateMessage(
+                            CreateInteractionResponseMessage::new()
+                                .content(format!("You chose {}!", picked.label()))
+                                .components(Vec::new()),
+                        ),
+                    )
+                    .await;
+
+                status
+                    .edit(
+                        ctx,
+                        EditMessage::new().embed(status_embed.clone().field(
+                            "Locked In",
+                            locked_in(players, &moves),
+                            false,
+                        )),
+                    )
+                    .await?;
+
+                if moves.iter().all(Option::is_some) {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        let _ = interaction
+            .create_response(ctx, CreateInteractionResponse::Message(response))
+            .await;
+    }
+
+    status
+        .edit(ctx, EditMessage::new().components(Vec::new()))
+        .await?;
+
+    Ok(moves)
+}
+
+fn locked_in<M>(players: [&Player; 2], moves: &[Option<M>; 2]) -> String {
+    players
+        .iter()
+        .zip(moves)
+        .map(|(player, picked)| {
+            let status = if picked.is_some() { "✅" } else { "⌛" };
+            format!("{status} {}", player.nick)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
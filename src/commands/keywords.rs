@@ -0,0 +1,447 @@
+use poise::CreateReply;
+use poise::serenity_prelude::{
+    CreateEmbed, CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+use std::time::Duration;
+use tracing::{debug, info, instrument};
+
+use crate::custom_types::command::{Context, Error, KeywordResponse, KeywordRule};
+use crate::db::Database;
+use crate::throttle::MAX_LIMIT_WINDOW;
+use crate::utils::message::embed::base_embed;
+use crate::utils::message::interaction::{page_buttons, wait_for_user_interaction};
+use crate::utils::message::send_simple_ephemeral;
+
+// Discord caps embeds at 25 fields, which includes the page field, field names at 256 characters
+// and values at 1024. A message can only hold 6000 characters, so pages stop well short of that
+// to leave room for the title, author and footer.
+const FIELDS_PER_PAGE: usize = 24;
+const MAX_FIELD_NAME_LEN: usize = 256;
+const MAX_FIELD_LEN: usize = 1024;
+const MAX_PAGE_LEN: usize = 5000;
+const LIST_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[poise::command(
+    slash_command,
+    guild_only,
+    subcommands("add", "remove", "list", "edit", "limits"),
+    subcommand_required
+)]
+pub async fn keyword(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Add a new keyword response to this server
+#[poise::command(slash_command, required_permissions = "MANAGE_GUILD")]
+#[instrument(skip(ctx))]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "Comma separated keywords to respond to"] keywords: String,
+    #[description = "Responses separated by |, one is picked at random"] responses: String,
+    #[description = "Comma separated weights for each response"] weights: Option<String>,
+    #[description = "Seconds before this can respond again in the same channel"] cooldown: Option<
+        u64,
+    >,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let kwr = match parse_keyword_response(&keywords, &responses, weights.as_deref()) {
+        Ok(kwr) => kwr,
+        Err(reason) => {
+            send_simple_ephemeral(&ctx, &reason).await?;
+            return Ok(());
+        }
+    };
+
+    let db = Database::new(ctx);
+    // Make sure the guild has its starting keywords before adding to them
+    ctx.data().keywords.get(&db, guild_id).await?;
+
+    let rule = KeywordRule {
+        kwr,
+        cooldown: cooldown.filter(|c| *c > 0),
+    };
+    let id = db.add_keyword_response(guild_id, &rule).await?;
+    ctx.data().keywords.reload(&db, guild_id).await?;
+
+    info!(id, rule = ?rule, "Added keyword response");
+    ctx.say(format!(
+        "Added keyword response #{id} for {}",
+        rule.kwr.keywords().join(", ")
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Remove one of this server's keyword responses
+#[poise::command(slash_command, required_permissions = "MANAGE_GUILD")]
+#[instrument(skip(ctx))]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "The id shown in /keyword list"] id: i64,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let db = Database::new(ctx);
+
+    if db.remove_keyword_response(guild_id, id).await? {
+        ctx.data().keywords.reload(&db, guild_id).await?;
+        info!(id, "Removed keyword response");
+        ctx.say(format!("Removed keyword response #{id}")).await?;
+    } else {
+        send_simple_ephemeral(&ctx, &format!("There is no keyword response #{id}")).await?;
+    }
+
+    Ok(())
+}
+
+/// Change the keywords, responses or weights of one of this server's keyword responses
+#[poise::command(slash_command, required_permissions = "MANAGE_GUILD")]
+#[instrument(skip(ctx))]
+pub async fn edit(
+    ctx: Context<'_>,
+    #[description = "The id shown in /keyword list"] id: i64,
+    #[description = "Comma separated keywords to respond to"] keywords: Option<String>,
+    #[description = "Responses separated by |, one is picked at random"] responses: Option<String>,
+    #[description = "Comma separated weights for each response"] weights: Option<String>,
+    #[description = "Pick responses evenly instead of by weight"] remove_weights: Option<bool>,
+    #[description = "Seconds before this can respond again in the same channel, 0 removes it"]
+    cooldown: Option<u64>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let db = Database::new(ctx);
+
+    let Some((_, old_rule)) = db
+        .get_keyword_responses(guild_id)
+        .await?
+        .into_iter()
+        .find(|(kwr_id, _)| *kwr_id == id)
+    else {
+        send_simple_ephemeral(&ctx, &format!("There is no keyword response #{id}")).await?;
+        return Ok(());
+    };
+
+    let old_kwr = &old_rule.kwr;
+    let keywords = keywords.unwrap_or_else(|| old_kwr.keywords().join(","));
+    let responses = responses.unwrap_or_else(|| old_kwr.responses().join("|"));
+    let weights = if remove_weights.unwrap_or(false) {
+        None
+    } else {
+        weights.or_else(|| old_kwr.weights().map(join_weights))
+    };
+
+    let kwr = match parse_keyword_response(&keywords, &responses, weights.as_deref()) {
+        Ok(kwr) => kwr,
+        Err(reason) => {
+            send_simple_ephemeral(&ctx, &reason).await?;
+            return Ok(());
+        }
+    };
+
+    let rule = KeywordRule {
+        kwr,
+        cooldown: cooldown.or(old_rule.cooldown).filter(|c| *c > 0),
+    };
+
+    db.update_keyword_response(guild_id, id, &rule).await?;
+    ctx.data().keywords.reload(&db, guild_id).await?;
+
+    info!(id, rule = ?rule, "Edited keyword response");
+    ctx.say(format!("Updated keyword response #{id}")).await?;
+
+    Ok(())
+}
+
+/// See every keyword response in this server
+#[poise::command(slash_command)]
+#[instrument(skip(ctx))]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let db = Database::new(ctx);
+
+    ctx.data().keywords.get(&db, guild_id).await?;
+    let kwrs = db.get_keyword_responses(guild_id).await?;
+
+    if kwrs.is_empty() {
+        send_simple_ephemeral(&ctx, "This server has no keyword responses").await?;
+        return Ok(());
+    }
+
+    let pages = keyword_pages(kwrs.iter().map(|(id, rule)| keyword_field(*id, rule)));
+    let page_count = pages.len() as i64;
+    let mut page = 0;
+    let mut embed = keyword_embed(&pages[0], page, page_count);
+
+    let reply = ctx
+        .send(
+            CreateReply::default()
+                .embed(embed.clone())
+                .components(page_buttons(page, page_count))
+                .ephemeral(true),
+        )
+        .await?;
+    let message = reply.message().await?.into_owned();
+
+    while let Some(interaction) =
+        wait_for_user_interaction(&ctx, &message, ctx.author().id, LIST_TIMEOUT).await
+    {
+        match interaction.data.custom_id.as_str() {
+            "prev" => page = (page - 1).max(0),
+            "next" => page = (page + 1).min(page_count - 1),
+            _ => {}
+        }
+        debug!(page, "Changed keyword list page");
+
+        embed = keyword_embed(&pages[page as usize], page, page_count);
+        interaction
+            .create_response(
+                ctx,
+                CreateInteractionResponse::UpdateMessage(
+                    CreateInteractionResponseMessage::new()
+                        .embed(embed.clone())
+                        .components(page_buttons(page, page_count)),
+                ),
+            )
+            .await?;
+    }
+
+    reply
+        .edit(
+            ctx,
+            CreateReply::default().embed(embed).components(Vec::new()),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Set how often Johnson can respond to keywords and "I'm" messages in this server
+#[poise::command(slash_command, required_permissions = "MANAGE_GUILD")]
+#[instrument(skip(ctx))]
+pub async fn limits(
+    ctx: Context<'_>,
+    #[description = "Max responses in a channel per window, 0 removes the limit"]
+    channel_limit: Option<u32>,
+    #[description = "Max responses to one person per window, 0 removes the limit"]
+    user_limit: Option<u32>,
+    #[description = "How long the window is in seconds"] window: Option<u64>,
+    #[description = "Seconds between dad jokes in a channel, 0 removes the cooldown"]
+    dad_bot_cooldown: Option<u64>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let db = Database::new(ctx);
+
+    // Also makes sure there is a server config to read from
+    let old_limits = ctx.data().keywords.get(&db, guild_id).await?.limits.clone();
+
+    let channel_limit = channel_limit
+        .map(u64::from)
+        .or(old_limits.channel_limit.map(|l| l as u64))
+        .filter(|l| *l > 0);
+    let user_limit = user_limit
+        .map(u64::from)
+        .or(old_limits.user_limit.map(|l| l as u64))
+        .filter(|l| *l > 0);
+    let window = window
+        .unwrap_or(old_limits.window.as_secs())
+        .clamp(1, MAX_LIMIT_WINDOW.as_secs());
+    let dad_bot_cooldown = dad_bot_cooldown
+        .or(old_limits.dad_bot_cooldown.map(|c| c.as_secs()))
+        .filter(|c| *c > 0);
+
+    db.save_keyword_limits(
+        guild_id,
+        channel_limit.map(|l| l as i64),
+        user_limit.map(|l| l as i64),
+        Some(window as i64),
+        dad_bot_cooldown.map(|c| c as i64),
+    )
+    .await?;
+    let new_limits = ctx
+        .data()
+        .keywords
+        .reload(&db, guild_id)
+        .await?
+        .limits
+        .clone();
+
+    info!(limits = ?new_limits, "Saved keyword limits");
+    ctx.say(format!(
+        "Johnson can now respond {} times per channel and {} times per person every {window} seconds, and make a dad joke {}",
+        display_limit(new_limits.channel_limit),
+        display_limit(new_limits.user_limit),
+        new_limits
+            .dad_bot_cooldown
+            .map_or(String::from("whenever he wants"), |c| format!(
+                "every {} seconds",
+                c.as_secs()
+            ))
+    ))
+    .await?;
+
+    Ok(())
+}
+
+fn display_limit(limit: Option<usize>) -> String {
+    limit.map_or(String::from("unlimited"), |l| l.to_string())
+}
+
+/// A keyword response as an embed field, cut down to fit if it's too long
+fn keyword_field(id: i64, rule: &KeywordRule) -> (String, String) {
+    let kwr = &rule.kwr;
+    let mut value = match kwr.weights() {
+        Some(weights) => kwr
+            .responses()
+            .iter()
+            .zip(weights)
+            .map(|(response, weight)| format!("- ({weight}) {response}"))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        None => kwr
+            .responses()
+            .iter()
+            .map(|response| format!("- {response}"))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+    truncate(&mut value, MAX_FIELD_LEN);
+
+    let cooldown = rule
+        .cooldown
+        .map_or(String::new(), |c| format!(" ({c}s cooldown)"));
+    let mut name = format!("#{id}: {}{cooldown}", kwr.keywords().join(", "));
+    truncate(&mut name, MAX_FIELD_NAME_LEN);
+
+    (name, value)
+}
+
+/// Splits the fields into pages that fit in one embed each. There's always at least one page.
+fn keyword_pages(fields: impl Iterator<Item = (String, String)>) -> Vec<Vec<(String, String)>> {
+    let mut pages = vec![Vec::new()];
+    let mut page_len = 0;
+
+    for field in fields {
+        let field_len = field.0.len() + field.1.len();
+        let page = pages.last_mut().expect("pages is never empty");
+        if !page.is_empty()
+            && (page.len() == FIELDS_PER_PAGE || page_len + field_len > MAX_PAGE_LEN)
+        {
+            pages.push(Vec::new());
+            page_len = 0;
+        }
+
+        pages.last_mut().expect("pages is never empty").push(field);
+        page_len += field_len;
+    }
+
+    pages
+}
+
+fn keyword_embed(fields: &[(String, String)], page: i64, page_count: i64) -> CreateEmbed {
+    base_embed()
+        .title("Keyword Responses")
+        .fields(
+            fields
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone(), false)),
+        )
+        .field("Page", format!("{} / {page_count}", page + 1), false)
+}
+
+/// Cuts s down to at most max bytes, ending it with ... if anything was cut
+fn truncate(s: &mut String, max: usize) {
+    if s.len() <= max {
+        return;
+    }
+
+    let mut end = max - 3;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s.truncate(end);
+    s.push_str("...");
+}
+
+fn parse_keyword_response(
+    keywords: &str,
+    responses: &str,
+    weights: Option<&str>,
+) -> Result<KeywordResponse, String> {
+    let keywords: Vec<String> = split_list(keywords, ',')
+        .map(|kw| kw.to_lowercase())
+        .collect();
+    let responses: Vec<String> = split_list(responses, '|').map(String::from).collect();
+    let weights = weights
+        .map(|w| {
+            split_list(w, ',')
+                .map(|w| {
+                    w.parse::<f32>()
+                        .map_err(|_| format!("{w} is not a valid weight"))
+                })
+                .collect::<Result<Vec<f32>, String>>()
+        })
+        .transpose()?;
+
+    let kwr = KeywordResponse::from_parts(keywords, responses, weights);
+    kwr.validate()
+        .map_err(|reason| format!("That keyword response won't work, {reason}!"))?;
+
+    Ok(kwr)
+}
+
+fn split_list(list: &str, sep: char) -> impl Iterator<Item = &str> {
+    list.split(sep).map(str::trim).filter(|s| !s.is_empty())
+}
+
+fn join_weights(weights: &[f32]) -> String {
+    weights
+        .iter()
+        .map(f32::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(count: usize, len: usize) -> impl Iterator<Item = (String, String)> {
+        (0..count).map(move |i| (format!("#{i}"), "a".repeat(len)))
+    }
+
+    #[test]
+    fn pages_hold_at_most_a_page_of_fields() {
+        let pages = keyword_pages(fields(50, 10));
+
+        assert_eq!(
+            pages.iter().map(Vec::len).collect::<Vec<_>>(),
+            [FIELDS_PER_PAGE, FIELDS_PER_PAGE, 2]
+        );
+    }
+
+    #[test]
+    fn long_fields_start_new_pages() {
+        let pages = keyword_pages(fields(12, MAX_FIELD_LEN));
+
+        for page in &pages {
+            let len: usize = page
+                .iter()
+                .map(|(name, value)| name.len() + value.len())
+                .sum();
+            assert!(len <= MAX_PAGE_LEN, "page is {len} long");
+        }
+        assert_eq!(pages.iter().map(Vec::len).sum::<usize>(), 12);
+    }
+
+    #[test]
+    fn no_fields_is_one_empty_page() {
+        assert_eq!(keyword_pages(fields(0, 10)).len(), 1);
+    }
+
+    #[test]
+    fn truncate_keeps_char_boundaries() {
+        let mut s = "é".repeat(10);
+        truncate(&mut s, 8);
+
+        assert_eq!(s, "éé...");
+    }
+}
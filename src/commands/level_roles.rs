@@ -0,0 +1,170 @@
+use poise::CreateReply;
+use poise::serenity_prelude::{Mentionable, Role, UserId};
+use tracing::{debug, info, instrument};
+
+use crate::custom_types::command::{Context, Error};
+use crate::db::Database;
+use crate::levels::sync_level_roles;
+use crate::utils::message::embed::base_embed;
+use crate::utils::message::send_simple_ephemeral;
+
+#[poise::command(
+    slash_command,
+    guild_only,
+    subcommands("add", "remove", "list", "replace", "sync"),
+    subcommand_required
+)]
+pub async fn level_role(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Give out a role when someone reaches a level
+#[poise::command(slash_command, required_permissions = "MANAGE_ROLES")]
+#[instrument(skip(ctx))]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "The level that earns the role"] level: u32,
+    #[description = "The role to give out"] role: Role,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let db = Database::new(ctx);
+
+    db.save_level_role(guild_id, level.into(), role.id).await?;
+
+    info!(level, role = role.name, "Added level role");
+    ctx.say(format!(
+        "Members will now get {} at level {level}! Use /level_role sync to give it to everyone that already made it.",
+        role.mention()
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Stop giving out a role for reaching a level
+#[poise::command(slash_command, required_permissions = "MANAGE_ROLES")]
+#[instrument(skip(ctx))]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "The role to stop giving out"] role: Role,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let db = Database::new(ctx);
+
+    if db.remove_level_role(guild_id, role.id).await? {
+        info!(role = role.name, "Removed level role");
+        ctx.say(format!("{} is no longer a level role", role.mention()))
+            .await?;
+    } else {
+        send_simple_ephemeral(&ctx, "That isn't a level role!").await?;
+    }
+
+    Ok(())
+}
+
+/// See which roles are given out for reaching a level
+#[poise::command(slash_command)]
+#[instrument(skip(ctx))]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let db = Database::new(ctx);
+
+    let level_roles = db.get_level_roles(guild_id).await?;
+    if level_roles.is_empty() {
+        send_simple_ephemeral(&ctx, "This server has no level roles").await?;
+        return Ok(());
+    }
+
+    let replace = match db.get_server_conf(guild_id).await {
+        Ok(conf) => conf.replace_level_roles,
+        Err(sqlx::Error::RowNotFound) => false,
+        Err(err) => return Err(err.into()),
+    };
+
+    let lines = level_roles
+        .iter()
+        .map(|(level, role)| format!("**Level {level}**: {}", role.mention()))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let embed = base_embed().title("Level Roles").description(lines).field(
+        "Old Roles",
+        if replace {
+            "Taken away when a new one is earned"
+        } else {
+            "Kept when a new one is earned"
+        },
+        false,
+    );
+
+    ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+        .await?;
+
+    Ok(())
+}
+
+/// Choose whether members lose their old level roles when they earn a new one
+#[poise::command(slash_command, required_permissions = "MANAGE_ROLES")]
+#[instrument(skip(ctx))]
+pub async fn replace(
+    ctx: Context<'_>,
+    #[description = "Take old level roles away when a new one is earned?"] enabled: bool,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let db = Database::new(ctx);
+
+    db.save_replace_level_roles(guild_id, enabled).await?;
+
+    ctx.say(if enabled {
+        "Old level roles will be taken away when a new one is earned"
+    } else {
+        "Members will keep all of their level roles"
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Give everyone the level roles they have earned so far
+#[poise::command(slash_command, required_permissions = "MANAGE_ROLES")]
+#[instrument(skip(ctx))]
+pub async fn sync(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let db = Database::new(ctx);
+
+    // This makes a couple of requests per member, it can take a while
+    ctx.defer().await?;
+
+    let curve = db.get_xp_curve(guild_id).await?;
+    let mut synced = 0;
+    let mut added = 0;
+    let mut removed = 0;
+
+    for db_user in db.get_members(guild_id).await? {
+        let member = match guild_id.member(ctx, UserId::new(db_user.id as u64)).await {
+            Ok(member) => member,
+            Err(err) => {
+                debug!("Skipping {}, they probably left: {err}", db_user.name);
+                continue;
+            }
+        };
+
+        if member.user.bot {
+            continue;
+        }
+
+        let changes = sync_level_roles(ctx.http(), &db, &member, curve.level(db_user.exp)).await?;
+
+        synced += 1;
+        added += changes.added.len();
+        removed += changes.removed.len();
+    }
+
+    info!(synced, added, removed, "Synced level roles");
+    ctx.say(format!(
+        "Synced level roles for {synced} members, giving out {added} roles and taking away {removed}"
+    ))
+    .await?;
+
+    Ok(())
+}
@@ -1,6 +1,9 @@
 pub mod basic;
 pub mod config;
+pub mod economy;
 pub mod gamble;
+pub mod keywords;
+pub mod level_roles;
 pub mod relations;
 pub mod roles;
 pub mod stats;
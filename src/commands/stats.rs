@@ -1,38 +1,266 @@
-use poise::CreateReply;
-use poise::serenity_prelude::User;
-use tracing::instrument;
+use std::time::Duration;
+
+use poise::serenity_prelude::{
+    CreateAttachment, CreateEmbed, CreateInteractionResponse, CreateInteractionResponseMessage,
+    GuildId, Mentionable, User, UserId,
+};
+use poise::{ChoiceParameter, CreateReply};
+use tracing::{debug, instrument};
 
 use crate::custom_types::command::{Context, Error};
-use crate::db::Database;
+use crate::db::{Database, RankBy, RelationType};
+use crate::levels::{LevelProgress, XpCurve};
+use crate::rank_card::{RankCard, progress_fraction};
 use crate::utils::message::embed::base_embed;
+use crate::utils::message::interaction::{page_buttons, wait_for_user_interaction};
+
+const LEADERBOARD_PAGE_SIZE: i64 = 10;
+const LEADERBOARD_TIMEOUT: Duration = Duration::from_secs(120);
+const PROGRESS_BAR_LEN: usize = 20;
+const RANK_CARD_FILE: &str = "rank.png";
 
-#[poise::command(slash_command)]
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum LeaderboardSort {
+    #[name = "XP"]
+    Xp,
+    Level,
+    #[name = "V-Bucks"]
+    VBucks,
+}
+
+impl LeaderboardSort {
+    // Level only ever goes up with XP, so they rank the same
+    fn rank_by(&self) -> RankBy {
+        match self {
+            LeaderboardSort::Xp | LeaderboardSort::Level => RankBy::Exp,
+            LeaderboardSort::VBucks => RankBy::VBucks,
+        }
+    }
+}
+
+#[poise::command(slash_command, guild_only)]
 #[instrument(skip(ctx))]
 pub async fn show_stats(
     ctx: Context<'_>,
     #[description = "Let the whole world know?"] annoy_others: bool,
     #[description = "Who's stats would you like to see. By default this is you."]
     user_to_show: Option<User>,
+    #[description = "Show a rank card too?"] rank_card: Option<bool>,
 ) -> Result<(), Error> {
     let db_handler = Database::new(ctx);
+    let guild_id = ctx.guild_id().expect("not used in DM");
     let user = user_to_show.unwrap_or_else(|| ctx.author().to_owned());
-    let user_info = db_handler.get_user(&user).await?;
+    let user_info = db_handler.get_user(guild_id, &user).await?;
+    let progress = db_handler
+        .get_xp_curve(guild_id)
+        .await?
+        .progress(user_info.exp);
+    let rank = db_handler
+        .get_user_rank(guild_id, &user, RankBy::Exp)
+        .await?;
+    let member_count = db_handler.get_member_count(guild_id).await?;
+
+    let relations = db_handler.get_relations(&user).await?;
+    let friends = relations
+        .iter()
+        .filter(|(_, r)| *r == RelationType::Friend)
+        .count();
+    let enemies = relations
+        .iter()
+        .filter(|(_, r)| *r == RelationType::Blocked)
+        .count();
 
     let stat_fields = vec![
-        ("XP", format!("*{}*", user_info.exp), false),
-        ("V-Bucks", format!("*{}*", user_info.vbucks), false),
+        ("Level", format!("*{}*", progress.level), true),
+        (
+            "Rank",
+            rank.map_or(String::from("*Unranked*"), |r| {
+                format!("*#{r} out of {member_count}*")
+            }),
+            true,
+        ),
+        ("XP", format!("*{}*", user_info.exp), true),
+        (
+            "Next Level",
+            format!(
+                "{} *{} / {} XP, {} to go*",
+                progress_bar(&progress),
+                progress.exp_into_level,
+                progress.exp_for_next,
+                progress.exp_for_next - progress.exp_into_level
+            ),
+            false,
+        ),
+        ("V-Bucks", format!("*{}*", user_info.vbucks), true),
+        ("Friends", format!("*{friends}*"), true),
+        ("Enemies", format!("*{enemies}*"), true),
     ];
 
-    let stats_embed = base_embed()
+    let mut stats_embed = base_embed()
         .title(format!("{}'s Stats", user.name))
         .fields(stat_fields);
+    let mut reply = CreateReply::default().reply(true).ephemeral(!annoy_others);
+
+    if rank_card.unwrap_or(false) {
+        let png = RankCard {
+            name: &user.name,
+            progress,
+            rank,
+            member_count,
+        }
+        .render()?;
+
+        reply = reply.attachment(CreateAttachment::bytes(png, RANK_CARD_FILE));
+        stats_embed = stats_embed.image(format!("attachment://{RANK_CARD_FILE}"));
+    }
+
+    ctx.send(reply.embed(stats_embed)).await?;
+    Ok(())
+}
+
+fn progress_bar(progress: &LevelProgress) -> String {
+    let filled = (progress_fraction(progress) * PROGRESS_BAR_LEN as f64) as usize;
+
+    format!(
+        "`{}{}`",
+        "▰".repeat(filled),
+        "▱".repeat(PROGRESS_BAR_LEN - filled)
+    )
+}
+
+#[poise::command(slash_command, guild_only)]
+#[instrument(skip(ctx))]
+pub async fn leaderboard(
+    ctx: Context<'_>,
+    #[description = "What to rank everyone by. By default this is XP."] sort_by: Option<
+        LeaderboardSort,
+    >,
+) -> Result<(), Error> {
+    let db_handler = Database::new(ctx);
+    let guild_id = ctx.guild_id().expect("not used in DM");
+    let sort_by = sort_by.unwrap_or(LeaderboardSort::Xp);
+    let author = ctx.author();
+
+    // Make sure the caller is on the leaderboard before looking for them
+    db_handler.get_user(guild_id, author).await?;
+    let member_count = db_handler.get_member_count(guild_id).await?;
+    let curve = db_handler.get_xp_curve(guild_id).await?;
+    let author_rank = db_handler
+        .get_user_rank(guild_id, author, sort_by.rank_by())
+        .await?;
 
-    ctx.send(
-        CreateReply::default()
-            .embed(stats_embed)
-            .reply(true)
-            .ephemeral(!annoy_others),
+    let page_count = ((member_count + LEADERBOARD_PAGE_SIZE - 1) / LEADERBOARD_PAGE_SIZE).max(1);
+    let author_rank = author_rank.map_or(String::from("Unranked"), |r| {
+        format!("#{r} out of {member_count}")
+    });
+    let mut page = 0;
+
+    let mut embed = leaderboard_embed(
+        &db_handler,
+        guild_id,
+        sort_by,
+        &curve,
+        page,
+        page_count,
+        &author_rank,
     )
     .await?;
+
+    let reply = ctx
+        .send(
+            CreateReply::default()
+                .embed(embed.clone())
+                .components(page_buttons(page, page_count)),
+        )
+        .await?;
+    let message = reply.message().await?.into_owned();
+
+    while let Some(interaction) =
+        wait_for_user_interaction(&ctx, &message, author.id, LEADERBOARD_TIMEOUT).await
+    {
+        match interaction.data.custom_id.as_str() {
+            "prev" => page = (page - 1).max(0),
+            "next" => page = (page + 1).min(page_count - 1),
+            _ => {}
+        }
+        debug!(page, "Changed leaderboard page");
+
+        embed = leaderboard_embed(
+            &db_handler,
+            guild_id,
+            sort_by,
+            &curve,
+            page,
+            page_count,
+            &author_rank,
+        )
+        .await?;
+
+        interaction
+            .create_response(
+                ctx,
+                CreateInteractionResponse::UpdateMessage(
+                    CreateInteractionResponseMessage::new()
+                        .embed(embed.clone())
+                        .components(page_buttons(page, page_count)),
+                ),
+            )
+            .await?;
+    }
+
+    // Nobody is paging anymore, take the buttons away
+    reply
+        .edit(
+            ctx,
+            CreateReply::default().embed(embed).components(Vec::new()),
+        )
+        .await?;
+
     Ok(())
 }
+
+async fn leaderboard_embed(
+    db_handler: &Database<'_>,
+    guild_id: GuildId,
+    sort_by: LeaderboardSort,
+    curve: &XpCurve,
+    page: i64,
+    page_count: i64,
+    author_rank: &str,
+) -> Result<CreateEmbed, Error> {
+    let ranked = db_handler
+        .get_leaderboard(
+            guild_id,
+            sort_by.rank_by(),
+            LEADERBOARD_PAGE_SIZE,
+            page * LEADERBOARD_PAGE_SIZE,
+        )
+        .await?;
+
+    let lines = ranked
+        .iter()
+        .map(|(rank, db_user)| {
+            let score = match sort_by {
+                LeaderboardSort::Xp => format!("{} XP", db_user.exp),
+                LeaderboardSort::Level => format!("Level {}", curve.level(db_user.exp)),
+                LeaderboardSort::VBucks => format!("${}", db_user.vbucks),
+            };
+
+            format!(
+                "**#{rank}** {} - {score}",
+                UserId::new(db_user.id as u64).mention()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Ok(base_embed()
+        .title(format!("{} Leaderboard", sort_by.name()))
+        .description(if lines.is_empty() {
+            String::from("Nobody is here :(")
+        } else {
+            lines
+        })
+        .field("Page", format!("{} / {page_count}", page + 1), false)
+        .field("Your Rank", author_rank, false))
+}
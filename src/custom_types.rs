@@ -1,13 +1,15 @@
 pub mod command {
+    use crate::keywords::KeywordCache;
+    use crate::rng::RngProvider;
     use crate::serenity::prelude::TypeMapKey;
-    use poise::serenity_prelude::{OnlineStatus, Role, VoiceState};
+    use crate::throttle::ResponseThrottle;
+    use poise::serenity_prelude::{OnlineStatus, VoiceState};
     use problemo::Problem;
-    use reqwest::Client as HttpClient;
     use serde::Deserialize;
     use sqlx::SqlitePool;
+    use std::fmt::{Display, Formatter};
 
-    #[derive(Debug, Deserialize, Clone)]
-    #[serde(untagged)]
+    #[derive(Debug, Clone)]
     pub enum KeywordResponse {
         SingleKW {
             kw: String,
@@ -37,6 +39,215 @@ pub mod command {
         },
     }
 
+    /// A keyword response along with how often it's allowed to go off
+    #[derive(Debug, Deserialize, Clone)]
+    #[serde(try_from = "RawKeywordResponse")]
+    pub struct KeywordRule {
+        pub kwr: KeywordResponse,
+        /// Seconds before this can respond again in the same channel
+        pub cooldown: Option<u64>,
+    }
+
+    /// The shape of a keyword response as written in kwr.json. Every combination of fields is
+    /// accepted here so that ambiguous ones can be rejected with a useful error instead of
+    /// quietly being read as the wrong variant.
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct RawKeywordResponse {
+        kw: Option<String>,
+        kws: Option<Vec<String>>,
+        response: Option<String>,
+        responses: Option<Vec<String>>,
+        weights: Option<Vec<f32>>,
+        cooldown: Option<u64>,
+    }
+
+    impl TryFrom<RawKeywordResponse> for KeywordRule {
+        type Error = String;
+
+        fn try_from(raw: RawKeywordResponse) -> Result<Self, Self::Error> {
+            let kwr = match (raw.kw, raw.kws, raw.response, raw.responses, raw.weights) {
+                (Some(_), Some(_), _, _, _) => Err(String::from("has both kw and kws")),
+                (None, None, _, _, _) => Err(String::from("needs either kw or kws")),
+                (_, _, Some(_), Some(_), _) => Err(String::from("has both response and responses")),
+                (_, _, None, None, _) => Err(String::from("needs either response or responses")),
+                (_, _, Some(_), None, Some(_)) => {
+                    Err(String::from("weights can only be used with responses"))
+                }
+                (Some(kw), None, Some(response), None, None) => {
+                    Ok(KeywordResponse::SingleKW { kw, response })
+                }
+                (None, Some(kws), Some(response), None, None) => {
+                    Ok(KeywordResponse::MultiKW { kws, response })
+                }
+                (Some(kw), None, None, Some(responses), None) => {
+                    Ok(KeywordResponse::MultiResponse { kw, responses })
+                }
+                (None, Some(kws), None, Some(responses), None) => {
+                    Ok(KeywordResponse::MultiKWResponse { kws, responses })
+                }
+                (Some(kw), None, None, Some(responses), Some(weights)) => {
+                    Ok(KeywordResponse::WeightedResponses {
+                        kw,
+                        responses,
+                        weights,
+                    })
+                }
+                (None, Some(kws), None, Some(responses), Some(weights)) => {
+                    Ok(KeywordResponse::MultiKWWeightedResponses {
+                        kws,
+                        responses,
+                        weights,
+                    })
+                }
+            }?;
+
+            Ok(KeywordRule {
+                kwr,
+                cooldown: raw.cooldown,
+            })
+        }
+    }
+
+    /// Why a keyword response can't be used
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum KeywordResponseError {
+        NoKeywords,
+        EmptyKeyword,
+        NoResponses,
+        EmptyResponse,
+        WeightCount { weights: usize, responses: usize },
+        InvalidWeight(f32),
+        AllWeightsZero,
+    }
+
+    impl Display for KeywordResponseError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                KeywordResponseError::NoKeywords => write!(f, "there are no keywords"),
+                KeywordResponseError::EmptyKeyword => write!(f, "one of the keywords is blank"),
+                KeywordResponseError::NoResponses => write!(f, "there are no responses"),
+                KeywordResponseError::EmptyResponse => {
+                    write!(f, "one of the responses is blank")
+                }
+                KeywordResponseError::WeightCount { weights, responses } => {
+                    write!(f, "there are {weights} weights for {responses} responses")
+                }
+                KeywordResponseError::InvalidWeight(weight) => {
+                    write!(
+                        f,
+                        "{weight} is not a valid weight, weights can't be negative"
+                    )
+                }
+                KeywordResponseError::AllWeightsZero => write!(f, "every weight is zero"),
+            }
+        }
+    }
+
+    impl KeywordResponse {
+        /// Checks that a response can always be picked for this keyword response. Anything that
+        /// passes this is safe to hand to random_choice_weighted/random_choice_unweighted.
+        pub fn validate(&self) -> Result<(), KeywordResponseError> {
+            if self.keywords().is_empty() {
+                return Err(KeywordResponseError::NoKeywords);
+            }
+
+            if self.keywords().iter().any(|kw| kw.trim().is_empty()) {
+                return Err(KeywordResponseError::EmptyKeyword);
+            }
+
+            if self.responses().is_empty() {
+                return Err(KeywordResponseError::NoResponses);
+            }
+
+            if self.responses().iter().any(|r| r.trim().is_empty()) {
+                return Err(KeywordResponseError::EmptyResponse);
+            }
+
+            if let Some(weights) = self.weights() {
+                if weights.len() != self.responses().len() {
+                    return Err(KeywordResponseError::WeightCount {
+                        weights: weights.len(),
+                        responses: self.responses().len(),
+                    });
+                }
+
+                if let Some(weight) = weights.iter().find(|w| !w.is_finite() || **w < 0.0) {
+                    return Err(KeywordResponseError::InvalidWeight(*weight));
+                }
+
+                if weights.iter().all(|w| *w == 0.0) {
+                    return Err(KeywordResponseError::AllWeightsZero);
+                }
+            }
+
+            Ok(())
+        }
+
+        /// Builds the variant that fits the given keywords, responses and weights
+        pub fn from_parts(
+            mut kws: Vec<String>,
+            mut responses: Vec<String>,
+            weights: Option<Vec<f32>>,
+        ) -> KeywordResponse {
+            match (kws.len(), responses.len(), weights) {
+                (1, _, Some(weights)) => KeywordResponse::WeightedResponses {
+                    kw: kws.remove(0),
+                    responses,
+                    weights,
+                },
+                (_, _, Some(weights)) => KeywordResponse::MultiKWWeightedResponses {
+                    kws,
+                    responses,
+                    weights,
+                },
+                (1, 1, None) => KeywordResponse::SingleKW {
+                    kw: kws.remove(0),
+                    response: responses.remove(0),
+                },
+                (_, 1, None) => KeywordResponse::MultiKW {
+                    kws,
+                    response: responses.remove(0),
+                },
+                (1, _, None) => KeywordResponse::MultiResponse {
+                    kw: kws.remove(0),
+                    responses,
+                },
+                (_, _, None) => KeywordResponse::MultiKWResponse { kws, responses },
+            }
+        }
+
+        pub fn keywords(&self) -> &[String] {
+            match self {
+                KeywordResponse::SingleKW { kw, .. }
+                | KeywordResponse::MultiResponse { kw, .. }
+                | KeywordResponse::WeightedResponses { kw, .. } => std::slice::from_ref(kw),
+                KeywordResponse::MultiKW { kws, .. }
+                | KeywordResponse::MultiKWResponse { kws, .. }
+                | KeywordResponse::MultiKWWeightedResponses { kws, .. } => kws,
+            }
+        }
+
+        pub fn responses(&self) -> &[String] {
+            match self {
+                KeywordResponse::SingleKW { response, .. }
+                | KeywordResponse::MultiKW { response, .. } => std::slice::from_ref(response),
+                KeywordResponse::MultiResponse { responses, .. }
+                | KeywordResponse::MultiKWResponse { responses, .. }
+                | KeywordResponse::WeightedResponses { responses, .. }
+                | KeywordResponse::MultiKWWeightedResponses { responses, .. } => responses,
+            }
+        }
+
+        pub fn weights(&self) -> Option<&[f32]> {
+            match self {
+                KeywordResponse::WeightedResponses { weights, .. }
+                | KeywordResponse::MultiKWWeightedResponses { weights, .. } => Some(weights),
+                _ => None,
+            }
+        }
+    }
+
     #[derive(Clone)]
     pub struct FriendInfo {
         pub status: OnlineStatus,
@@ -58,14 +269,14 @@ pub mod command {
     // Custom data to send between commands
     pub struct Data {
         pub db_conn: SqlitePool,
-        pub kwr: Vec<KeywordResponse>,
-        pub http: HttpClient,
+        pub keywords: KeywordCache,
+        pub throttle: ResponseThrottle,
+        pub rng: RngProvider,
     }
 
     pub struct PartialData {
         pub db_conn: SqlitePool,
-        pub kwr: Vec<KeywordResponse>,
-        pub welcome_role: Option<Role>,
+        pub rng: RngProvider,
         pub friend_info: FriendInfo,
     }
 
@@ -89,14 +300,47 @@ pub mod mongo_schema {
         pub name: String,
         // user's discord id
         pub id: i64,
+        // guild these vbucks and exp belong to
+        pub guild_id: i64,
         pub vbucks: i64,
         pub exp: i64,
     }
 
+    /// A single change to a member's V-Bucks
+    #[derive(Debug, Clone, FromRow)]
+    pub struct LedgerEntry {
+        pub amount: i64,
+        pub reason: String,
+        pub counterparty: Option<i64>,
+        pub created_at: i64,
+        pub currency: String,
+    }
+
     #[derive(Debug, Serialize, Deserialize, Clone)]
     pub struct ServerConfig {
         pub id: i64,
         pub welcome_role_id: Option<i64>,
         pub error_channel_id: Option<i64>,
+        pub keywords_seeded: bool,
+        pub kw_channel_limit: Option<i64>,
+        pub kw_user_limit: Option<i64>,
+        pub kw_limit_window: Option<i64>,
+        pub dad_bot_cooldown: Option<i64>,
+        pub xp_cooldown: Option<i64>,
+        pub xp_min_length: Option<i64>,
+        pub replace_level_roles: bool,
+        pub level_up_mode: i64,
+        pub level_up_channel_id: Option<i64>,
+        pub level_up_message: Option<String>,
+        pub xp_curve: Option<String>,
+        pub money_min: Option<i64>,
+        pub money_max: Option<i64>,
+        pub exp_min: Option<i64>,
+        pub exp_max: Option<i64>,
+        pub economy_admin_role_id: Option<i64>,
+        pub audit_channel_id: Option<i64>,
+        pub house_bankroll: i64,
+        pub house_edge: Option<i64>,
+        pub rps_adaptive: bool,
     }
 }
@@ -1,23 +1,26 @@
+use crate::claims::{ClaimKind, ClaimOutcome};
 use crate::custom_types::command::Context as JContext;
+use crate::custom_types::command::KeywordResponse;
+use crate::custom_types::command::KeywordRule;
 use crate::custom_types::command::SerenityCtxData;
 use crate::custom_types::mongo_schema::DbUser;
+use crate::custom_types::mongo_schema::LedgerEntry;
 use crate::custom_types::mongo_schema::ServerConfig;
+use crate::levels::XpCurve;
 
-use std::f64::consts::E;
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::fmt::Error;
 use std::fmt::Formatter;
+use std::ops::RangeInclusive;
 
 use poise::serenity_prelude::{ChannelId, Context, GuildId, RoleId, User, UserId};
+use sqlx::SqliteConnection;
 use sqlx::SqlitePool;
 use sqlx::sqlite::SqliteQueryResult;
 use tracing::instrument;
 use tracing::{debug, info};
 
-const XP_MULTIPLIER: f64 = 15566f64;
-const XP_TRANSLATION: f64 = 15000f64;
-const EXPO_MULTIPLIER: f64 = 0.0415;
-
 #[derive(Debug, Clone)]
 pub enum ContextType<'a> {
     Slash(JContext<'a>),
@@ -68,6 +71,236 @@ impl Display for RelationType {
     }
 }
 
+/// Where a guild's level up messages get sent
+#[repr(u8)]
+#[derive(Eq, PartialEq, Clone, Copy, Debug, poise::ChoiceParameter)]
+pub enum LevelUpMode {
+    #[name = "Reply to the message"]
+    Reply = 0,
+    #[name = "Send to a channel"]
+    Channel,
+    #[name = "DM the member"]
+    Direct,
+    #[name = "Don't send anything"]
+    Silent,
+}
+
+impl LevelUpMode {
+    pub fn from_u8(t: u8) -> LevelUpMode {
+        match t {
+            1 => LevelUpMode::Channel,
+            2 => LevelUpMode::Direct,
+            3 => LevelUpMode::Silent,
+            _ => LevelUpMode::Reply,
+        }
+    }
+}
+
+/// Why a member's V-Bucks changed. Stored by its as_str name in the reason column of the ledger
+/// and of every transfer in the transactions table, whose migration still calls this
+/// TransferReason.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum LedgerReason {
+    /// Balance copied over from before V-Bucks were tracked per guild
+    CarriedOver,
+    MessageReward,
+    SlotsBet,
+    SlotsWin,
+    RpsWin,
+    RpsTournamentEntry,
+    RpsTournamentWin,
+    RpsHouse,
+    RpsWager,
+    EscrowRefund,
+    BlackjackBet,
+    BlackjackPayout,
+    CoinflipWager,
+    CoinflipWin,
+    CoinflipHouse,
+    DiceWager,
+    DiceWin,
+    DiceHouse,
+    Payment,
+    Gift,
+    DailyClaim,
+    WeeklyClaim,
+    /// Changed by hand with /economy
+    Admin,
+}
+
+impl LedgerReason {
+    const ALL: [LedgerReason; 23] = [
+        LedgerReason::CarriedOver,
+        LedgerReason::MessageReward,
+        LedgerReason::SlotsBet,
+        LedgerReason::SlotsWin,
+        LedgerReason::RpsWin,
+        LedgerReason::RpsTournamentEntry,
+        LedgerReason::RpsTournamentWin,
+        LedgerReason::RpsHouse,
+        LedgerReason::RpsWager,
+        LedgerReason::EscrowRefund,
+        LedgerReason::BlackjackBet,
+        LedgerReason::BlackjackPayout,
+        LedgerReason::CoinflipWager,
+        LedgerReason::CoinflipWin,
+        LedgerReason::CoinflipHouse,
+        LedgerReason::DiceWager,
+        LedgerReason::DiceWin,
+        LedgerReason::DiceHouse,
+        LedgerReason::Payment,
+        LedgerReason::Gift,
+        LedgerReason::DailyClaim,
+        LedgerReason::WeeklyClaim,
+        LedgerReason::Admin,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LedgerReason::CarriedOver => "carried_over",
+            LedgerReason::MessageReward => "message_reward",
+            LedgerReason::SlotsBet => "slots_bet",
+            LedgerReason::SlotsWin => "slots_win",
+            LedgerReason::RpsWin => "rps_win",
+            LedgerReason::RpsTournamentEntry => "rps_tournament_entry",
+            LedgerReason::RpsTournamentWin => "rps_tournament_win",
+            LedgerReason::RpsHouse => "rps_house",
+            LedgerReason::RpsWager => "rps_wager",
+            LedgerReason::EscrowRefund => "escrow_refund",
+            LedgerReason::BlackjackBet => "blackjack_bet",
+            LedgerReason::BlackjackPayout => "blackjack_payout",
+            LedgerReason::CoinflipWager => "coinflip_wager",
+            LedgerReason::CoinflipWin => "coinflip_win",
+            LedgerReason::CoinflipHouse => "coinflip_house",
+            LedgerReason::DiceWager => "dice_wager",
+            LedgerReason::DiceWin => "dice_win",
+            LedgerReason::DiceHouse => "dice_house",
+            LedgerReason::Payment => "payment",
+            LedgerReason::Gift => "gift",
+            LedgerReason::DailyClaim => "daily",
+            LedgerReason::WeeklyClaim => "weekly",
+            LedgerReason::Admin => "admin",
+        }
+    }
+
+    pub fn from_db(reason: &str) -> Option<LedgerReason> {
+        Self::ALL.into_iter().find(|r| r.as_str() == reason)
+    }
+}
+
+impl Display for LedgerReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            LedgerReason::CarriedOver => write!(f, "Carried over"),
+            LedgerReason::MessageReward => write!(f, "Chatting"),
+            LedgerReason::SlotsBet => write!(f, "Slots bet"),
+            LedgerReason::SlotsWin => write!(f, "Slots winnings"),
+            LedgerReason::RpsWin => write!(f, "Rock Paper Scissors"),
+            LedgerReason::RpsTournamentEntry => write!(f, "RPS tournament entry"),
+            LedgerReason::RpsTournamentWin => write!(f, "RPS tournament win"),
+            LedgerReason::RpsHouse => write!(f, "RPS against Johnson"),
+            LedgerReason::RpsWager => write!(f, "RPS wager"),
+            LedgerReason::EscrowRefund => write!(f, "Refunded wager"),
+            LedgerReason::BlackjackBet => write!(f, "Blackjack bet"),
+            LedgerReason::BlackjackPayout => write!(f, "Blackjack payout"),
+            LedgerReason::CoinflipWager => write!(f, "Coinflip wager"),
+            LedgerReason::CoinflipWin => write!(f, "Coinflip"),
+            LedgerReason::CoinflipHouse => write!(f, "Coinflip against Johnson"),
+            LedgerReason::DiceWager => write!(f, "Dice wager"),
+            LedgerReason::DiceWin => write!(f, "Dice"),
+            LedgerReason::DiceHouse => write!(f, "Dice against Johnson"),
+            LedgerReason::Payment => write!(f, "Payment"),
+            LedgerReason::Gift => write!(f, "Gift"),
+            LedgerReason::DailyClaim => write!(f, "Daily reward"),
+            LedgerReason::WeeklyClaim => write!(f, "Weekly reward"),
+            LedgerReason::Admin => write!(f, "Admin adjustment"),
+        }
+    }
+}
+
+/// The balances a member has in a guild
+#[derive(Eq, PartialEq, Clone, Copy, Debug, poise::ChoiceParameter)]
+pub enum Currency {
+    #[name = "V-Bucks"]
+    VBucks,
+    #[name = "XP"]
+    Exp,
+}
+
+impl Currency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Currency::VBucks => "vbucks",
+            Currency::Exp => "exp",
+        }
+    }
+}
+
+/// How an admin is changing a balance
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum BalanceUpdate {
+    Set(i64),
+    Add(i64),
+}
+
+#[derive(Debug)]
+pub enum TransferError {
+    /// The payer can't afford the transfer
+    InsufficientFunds {
+        balance: i64,
+        needed: i64,
+    },
+    NegativeAmount(i64),
+    /// Johnson's bankroll can't cover a payout
+    HouseCantCover {
+        bankroll: i64,
+        needed: i64,
+    },
+    /// One side of the transfer isn't in the members table
+    UnknownMember(UserId),
+    Db(sqlx::Error),
+}
+
+impl Display for TransferError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            TransferError::InsufficientFunds { balance, needed } => {
+                write!(f, "needed {needed} V-Bucks but only had {balance}")
+            }
+            TransferError::NegativeAmount(money) => {
+                write!(f, "can't transfer a negative amount ({money})")
+            }
+            TransferError::HouseCantCover { bankroll, needed } => {
+                write!(f, "house needed {needed} V-Bucks but only had {bankroll}")
+            }
+            TransferError::UnknownMember(user) => write!(f, "{user} is not a member"),
+            TransferError::Db(err) => write!(f, "database error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for TransferError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TransferError::Db(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for TransferError {
+    fn from(err: sqlx::Error) -> Self {
+        TransferError::Db(err)
+    }
+}
+
+/// What members get ranked by on the leaderboard
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum RankBy {
+    Exp,
+    VBucks,
+}
+
 impl RelationType {
     pub fn from_u8(t: u8) -> RelationType {
         match t {
@@ -93,161 +326,1533 @@ impl<'context> ContextWrapper<'context> {
         }
     }
 
-    fn new(ctx: impl Into<ContextType<'context>>) -> ContextWrapper<'context> {
-        let ctx: ContextType = ctx.into();
-        ContextWrapper { ctx }
-    }
-}
+    fn new(ctx: impl Into<ContextType<'context>>) -> ContextWrapper<'context> {
+        let ctx: ContextType = ctx.into();
+        ContextWrapper { ctx }
+    }
+}
+
+impl<'context> Database<'context> {
+    pub fn new(ctx: impl Into<ContextType<'context>>) -> Database<'context> {
+        Database {
+            ctx: ContextWrapper::new(ctx),
+        }
+    }
+
+    // Creates a user's member row in a guild, ignores if it already exists. A brand new member
+    // starts with whatever they had back when V-Bucks and XP were global.
+    #[instrument(skip(self))]
+    pub async fn create_user(&self, guild: GuildId, user: &User) -> sqlx::Result<DbUser> {
+        let pool = self.ctx.get_conn().await;
+        let user_id = user_to_id(user);
+        let guild_id = guild_to_id(guild);
+        let mut trans = pool.begin().await?;
+
+        sqlx::query!(
+            "INSERT or IGNORE into users (name, id, vbucks, exp) values($1, $2, $3, $4)",
+            user.name,
+            user_id,
+            0,
+            0
+        )
+        .execute(&mut *trans)
+        .await?;
+
+        // Balances from before they were tracked per guild are carried into the first guild the
+        // user shows up in, then cleared so no other guild gets a copy
+        let created = sqlx::query!(
+            "INSERT or IGNORE into members (guild_id, user_id, vbucks, exp) SELECT $1, id, vbucks, exp FROM users WHERE id = $2",
+            guild_id,
+            user_id
+        )
+        .execute(&mut *trans)
+        .await?;
+
+        if created.rows_affected() > 0 {
+            sqlx::query!(
+                "UPDATE users SET vbucks = 0, exp = 0 WHERE id = $1",
+                user_id
+            )
+            .execute(&mut *trans)
+            .await?;
+        }
+
+        let db_user = sqlx::query_as!(
+            DbUser,
+            "SELECT u.name, u.id, m.guild_id, m.vbucks, m.exp FROM members m JOIN users u ON u.id = m.user_id WHERE m.guild_id = $1 AND m.user_id = $2",
+            guild_id,
+            user_id
+        )
+        .fetch_one(&mut *trans)
+        .await?;
+
+        if created.rows_affected() > 0 && db_user.vbucks != 0 {
+            record_ledger(
+                &mut trans,
+                guild_id,
+                user_id,
+                db_user.vbucks,
+                LedgerReason::CarriedOver,
+                None,
+            )
+            .await?;
+        }
+
+        trans.commit().await?;
+
+        info!("Created user {:?}", db_user);
+
+        Ok(db_user)
+    }
+
+    // Gets a user's standing in a guild from db, creates one if they don't exist
+    #[instrument(skip(self))]
+    pub async fn get_user(&self, guild: GuildId, user: &User) -> sqlx::Result<DbUser> {
+        let pool = self.ctx.get_conn().await;
+        let user_id = user_to_id(user);
+        let guild_id = guild_to_id(guild);
+
+        let db_user = sqlx::query_as!(
+            DbUser,
+            "SELECT u.name, u.id, m.guild_id, m.vbucks, m.exp FROM members m JOIN users u ON u.id = m.user_id WHERE m.guild_id = $1 AND m.user_id = $2",
+            guild_id,
+            user_id
+        )
+        .fetch_optional(&pool)
+        .await?;
+
+        match db_user {
+            Some(x) => {
+                info!("Got db user: {:?}", x);
+                Ok(x)
+            }
+            None => self.create_user(guild, user).await,
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn give_user_money(
+        &self,
+        guild: GuildId,
+        user: &User,
+        money: i64,
+        reason: LedgerReason,
+    ) -> sqlx::Result<SqliteQueryResult> {
+        let pool = self.ctx.get_conn().await;
+        let user_id = user_to_id(user);
+        let guild_id = guild_to_id(guild);
+        let mut trans = pool.begin().await?;
+
+        // increment the money amount by "money" param
+        let res = sqlx::query!(
+            "UPDATE members SET vbucks = vbucks + $1 WHERE guild_id = $2 AND user_id = $3",
+            money,
+            guild_id,
+            user_id
+        )
+        .execute(&mut *trans)
+        .await?;
+
+        if res.rows_affected() > 0 {
+            record_ledger(&mut trans, guild_id, user_id, money, reason, None).await?;
+        }
+
+        trans.commit().await?;
+
+        Ok(res)
+    }
+
+    /// Takes V-Bucks from a member, failing without taking anything if they can't afford it
+    #[instrument(skip(self))]
+    pub async fn take_user_money(
+        &self,
+        guild: GuildId,
+        user: &User,
+        money: i64,
+        reason: LedgerReason,
+    ) -> Result<(), TransferError> {
+        if money < 0 {
+            return Err(TransferError::NegativeAmount(money));
+        }
+
+        let pool = self.ctx.get_conn().await;
+        let user_id = user_to_id(user);
+        let guild_id = guild_to_id(guild);
+        let mut trans = pool.begin().await?;
+
+        let debit = sqlx::query!(
+            "UPDATE members SET vbucks = vbucks - $1 WHERE guild_id = $2 AND user_id = $3 AND vbucks >= $1",
+            money,
+            guild_id,
+            user_id
+        )
+        .execute(&mut *trans)
+        .await?;
+
+        if debit.rows_affected() == 0 {
+            let balance = sqlx::query_scalar!(
+                "SELECT vbucks FROM members WHERE guild_id = $1 AND user_id = $2",
+                guild_id,
+                user_id
+            )
+            .fetch_optional(&mut *trans)
+            .await?;
+
+            return Err(match balance {
+                Some(balance) => TransferError::InsufficientFunds {
+                    balance,
+                    needed: money,
+                },
+                None => TransferError::UnknownMember(user.id),
+            });
+        }
+
+        record_ledger(&mut trans, guild_id, user_id, -money, reason, None).await?;
+
+        trans.commit().await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn give_user_exp(&self, guild: GuildId, user: &User, exp: i64) -> sqlx::Result<i64> {
+        let pool = self.ctx.get_conn().await;
+        let user_id = user_to_id(user);
+        let guild_id = guild_to_id(guild);
+
+        // increment the exp amount by "exp" param
+        let res = sqlx::query!(
+            "UPDATE members SET exp = exp + $1 WHERE guild_id = $2 AND user_id = $3 RETURNING exp",
+            exp,
+            guild_id,
+            user_id
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        Ok(res.exp)
+    }
+
+    /// Marks a message as rewarded if the user is off cooldown and it isn't the same as the last
+    /// one they were rewarded for. Returns false if the message shouldn't be rewarded. Checking
+    /// and claiming happen in one statement so two messages can't both slip through.
+    #[instrument(skip(self))]
+    pub async fn claim_message_reward(
+        &self,
+        guild: GuildId,
+        user: &User,
+        message_hash: i64,
+        cooldown: i64,
+    ) -> sqlx::Result<bool> {
+        let pool = self.ctx.get_conn().await;
+        let user_id = user_to_id(user);
+        let guild_id = guild_to_id(guild);
+
+        let res = sqlx::query!(
+            "
+            UPDATE members SET last_rewarded_at = unixepoch(), last_message_hash = $1
+            WHERE guild_id = $2 AND user_id = $3
+            AND (last_rewarded_at IS NULL OR last_rewarded_at + $4 <= unixepoch())
+            AND (last_message_hash IS NULL OR last_message_hash != $1)
+            ",
+            message_hash,
+            guild_id,
+            user_id,
+            cooldown
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Claims a daily or weekly reward for a member if they haven't already this period, giving
+    /// them V-Bucks based on their streak. The member has to exist already.
+    #[instrument(skip(self))]
+    pub async fn claim_reward(
+        &self,
+        guild: GuildId,
+        user: &User,
+        kind: ClaimKind,
+        now: i64,
+    ) -> sqlx::Result<ClaimOutcome> {
+        let pool = self.ctx.get_conn().await;
+        let mut trans = pool.begin().await?;
+        let user_id = user_to_id(user);
+        let guild_id = guild_to_id(guild);
+        let kind_id = kind as u8;
+
+        let last_claim = sqlx::query!(
+            "SELECT last_claimed_at, streak FROM claims WHERE guild_id = $1 AND user_id = $2 AND kind = $3",
+            guild_id,
+            user_id,
+            kind_id
+        )
+        .fetch_optional(&mut *trans)
+        .await?
+        .map(|c| (c.last_claimed_at, c.streak));
+
+        let Some(streak) = kind.next_streak(last_claim, now) else {
+            return Ok(ClaimOutcome::TooSoon {
+                next_claim_at: kind.next_claim_at(last_claim.map_or(now, |(at, _)| at)),
+            });
+        };
+        let reward = kind.reward(streak);
+        let last_claimed_at = last_claim.map(|(at, _)| at);
+
+        // Only goes through if nobody else claimed since we looked, so double clicking can't
+        // claim twice
+        let claimed = sqlx::query!(
+            "
+            INSERT INTO claims(guild_id, user_id, kind, last_claimed_at, streak)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT(guild_id, user_id, kind)
+            DO
+                UPDATE SET last_claimed_at = $4, streak = $5 WHERE last_claimed_at IS $6
+            ",
+            guild_id,
+            user_id,
+            kind_id,
+            now,
+            streak,
+            last_claimed_at
+        )
+        .execute(&mut *trans)
+        .await?;
+
+        if claimed.rows_affected() == 0 {
+            return Ok(ClaimOutcome::TooSoon {
+                next_claim_at: kind.next_claim_at(now),
+            });
+        }
+
+        sqlx::query!(
+            "UPDATE members SET vbucks = vbucks + $1 WHERE guild_id = $2 AND user_id = $3",
+            reward,
+            guild_id,
+            user_id
+        )
+        .execute(&mut *trans)
+        .await?;
+
+        let reason = match kind {
+            ClaimKind::Daily => LedgerReason::DailyClaim,
+            ClaimKind::Weekly => LedgerReason::WeeklyClaim,
+        };
+        record_ledger(&mut trans, guild_id, user_id, reward, reason, None).await?;
+
+        trans.commit().await?;
+
+        Ok(ClaimOutcome::Claimed {
+            reward,
+            streak,
+            next_claim_at: kind.next_claim_at(now),
+        })
+    }
+
+    /// Moves V-Bucks from one member to another and records it in the transactions table. Either
+    /// the whole transfer happens or none of it does; the payer is only debited if they can afford
+    /// it.
+    #[instrument(skip(self))]
+    pub async fn user_transaction(
+        &self,
+        guild: GuildId,
+        from_user: &User,
+        to_user: &User,
+        money: i64,
+        reason: LedgerReason,
+        note: Option<&str>,
+    ) -> Result<(), TransferError> {
+        if money < 0 {
+            return Err(TransferError::NegativeAmount(money));
+        }
+
+        let pool = self.ctx.get_conn().await;
+        let mut trans = pool.begin().await?;
+
+        let from_user_id = user_to_id(from_user);
+        let to_user_id = user_to_id(to_user);
+        let guild_id = guild_to_id(guild);
+
+        // The balance check has to happen in the same statement as the debit, otherwise two
+        // transfers at once could both pass the check
+        let debit = sqlx::query!(
+            "UPDATE members SET vbucks = vbucks - $1 WHERE guild_id = $2 AND user_id = $3 AND vbucks >= $1",
+            money,
+            guild_id,
+            from_user_id
+        )
+        .execute(&mut *trans)
+        .await?;
+
+        if debit.rows_affected() == 0 {
+            let balance = sqlx::query_scalar!(
+                "SELECT vbucks FROM members WHERE guild_id = $1 AND user_id = $2",
+                guild_id,
+                from_user_id
+            )
+            .fetch_optional(&mut *trans)
+            .await?;
+
+            // Dropping the transaction rolls it back
+            return Err(match balance {
+                Some(balance) => TransferError::InsufficientFunds {
+                    balance,
+                    needed: money,
+                },
+                None => TransferError::UnknownMember(from_user.id),
+            });
+        }
+
+        let credit = sqlx::query!(
+            "UPDATE members SET vbucks = vbucks + $1 WHERE guild_id = $2 AND user_id = $3",
+            money,
+            guild_id,
+            to_user_id,
+        )
+        .execute(&mut *trans)
+        .await?;
+
+        if credit.rows_affected() == 0 {
+            return Err(TransferError::UnknownMember(to_user.id));
+        }
+
+        let reason_str = reason.as_str();
+
+        sqlx::query!(
+            "
+            INSERT INTO transactions(guild_id, from_user, to_user, amount, reason, note)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ",
+            guild_id,
+            from_user_id,
+            to_user_id,
+            money,
+            reason_str,
+            note
+        )
+        .execute(&mut *trans)
+        .await?;
+
+        record_ledger(
+            &mut trans,
+            guild_id,
+            from_user_id,
+            -money,
+            reason,
+            Some(to_user_id),
+        )
+        .await?;
+        record_ledger(
+            &mut trans,
+            guild_id,
+            to_user_id,
+            money,
+            reason,
+            Some(from_user_id),
+        )
+        .await?;
+
+        trans.commit().await?;
+
+        info!(money, reason = reason_str, "Transferred V-Bucks");
+
+        Ok(())
+    }
+
+    /// Changes a member's V-Bucks or XP by hand, never taking them below 0. Returns the balance
+    /// before and after.
+    #[instrument(skip(self))]
+    pub async fn admin_update_balance(
+        &self,
+        guild: GuildId,
+        user: &User,
+        currency: Currency,
+        update: BalanceUpdate,
+        admin: &User,
+    ) -> sqlx::Result<(i64, i64)> {
+        let pool = self.ctx.get_conn().await;
+        let mut trans = pool.begin().await?;
+        let guild_id = guild_to_id(guild);
+        let user_id = user_to_id(user);
+        let admin_id = user_to_id(admin);
+
+        let balances = sqlx::query!(
+            "SELECT vbucks, exp FROM members WHERE guild_id = $1 AND user_id = $2",
+            guild_id,
+            user_id
+        )
+        .fetch_one(&mut *trans)
+        .await?;
+
+        let old = match currency {
+            Currency::VBucks => balances.vbucks,
+            Currency::Exp => balances.exp,
+        };
+        let new = match update {
+            BalanceUpdate::Set(amount) => amount,
+            BalanceUpdate::Add(amount) => old.saturating_add(amount),
+        }
+        .max(0);
+
+        match currency {
+            Currency::VBucks => {
+                sqlx::query!(
+                    "UPDATE members SET vbucks = $1 WHERE guild_id = $2 AND user_id = $3",
+                    new,
+                    guild_id,
+                    user_id
+                )
+                .execute(&mut *trans)
+                .await?
+            }
+            Currency::Exp => {
+                sqlx::query!(
+                    "UPDATE members SET exp = $1 WHERE guild_id = $2 AND user_id = $3",
+                    new,
+                    guild_id,
+                    user_id
+                )
+                .execute(&mut *trans)
+                .await?
+            }
+        };
+
+        if new != old {
+            record_ledger_in(
+                &mut trans,
+                guild_id,
+                user_id,
+                currency,
+                new - old,
+                LedgerReason::Admin,
+                Some(admin_id),
+            )
+            .await?;
+        }
+
+        trans.commit().await?;
+
+        info!(old, new, "Admin changed balance");
+
+        Ok((old, new))
+    }
+
+    /// Gets a page of a member's ledger, newest first
+    #[instrument(skip(self))]
+    pub async fn get_ledger(
+        &self,
+        guild: GuildId,
+        user: &User,
+        limit: i64,
+        offset: i64,
+    ) -> sqlx::Result<Vec<LedgerEntry>> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+        let user_id = user_to_id(user);
+
+        sqlx::query_as!(
+            LedgerEntry,
+            "
+            SELECT amount, reason, counterparty, created_at, currency FROM ledger
+            WHERE guild_id = $1 AND user_id = $2
+            ORDER BY id DESC
+            LIMIT $3 OFFSET $4
+            ",
+            guild_id,
+            user_id,
+            limit,
+            offset
+        )
+        .fetch_all(&pool)
+        .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_ledger_count(&self, guild: GuildId, user: &User) -> sqlx::Result<i64> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+        let user_id = user_to_id(user);
+
+        sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM ledger WHERE guild_id = $1 AND user_id = $2",
+            guild_id,
+            user_id
+        )
+        .fetch_one(&pool)
+        .await
+    }
+
+    /// Gets a page of a guild's members, best first, along with their rank. Members that are tied
+    /// share a rank.
+    #[instrument(skip(self))]
+    pub async fn get_leaderboard(
+        &self,
+        guild: GuildId,
+        rank_by: RankBy,
+        limit: i64,
+        offset: i64,
+    ) -> sqlx::Result<Vec<(i64, DbUser)>> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+
+        // The order has to be spelled out for each query to be checked at compile time
+        let ranked = match rank_by {
+            RankBy::Exp => sqlx::query!(
+                r#"
+                SELECT u.name, u.id, m.guild_id, m.vbucks, m.exp, RANK() OVER (ORDER BY m.exp DESC) AS "rank!: i64"
+                FROM members m JOIN users u ON u.id = m.user_id
+                WHERE m.guild_id = $1
+                ORDER BY m.exp DESC, m.user_id
+                LIMIT $2 OFFSET $3
+                "#,
+                guild_id,
+                limit,
+                offset
+            )
+            .fetch_all(&pool)
+            .await?
+            .into_iter()
+            .map(|r| {
+                (
+                    r.rank,
+                    DbUser {
+                        name: r.name,
+                        id: r.id,
+                        guild_id: r.guild_id,
+                        vbucks: r.vbucks,
+                        exp: r.exp,
+                    },
+                )
+            })
+            .collect(),
+            RankBy::VBucks => sqlx::query!(
+                r#"
+                SELECT u.name, u.id, m.guild_id, m.vbucks, m.exp, RANK() OVER (ORDER BY m.vbucks DESC) AS "rank!: i64"
+                FROM members m JOIN users u ON u.id = m.user_id
+                WHERE m.guild_id = $1
+                ORDER BY m.vbucks DESC, m.user_id
+                LIMIT $2 OFFSET $3
+                "#,
+                guild_id,
+                limit,
+                offset
+            )
+            .fetch_all(&pool)
+            .await?
+            .into_iter()
+            .map(|r| {
+                (
+                    r.rank,
+                    DbUser {
+                        name: r.name,
+                        id: r.id,
+                        guild_id: r.guild_id,
+                        vbucks: r.vbucks,
+                        exp: r.exp,
+                    },
+                )
+            })
+            .collect(),
+        };
+
+        Ok(ranked)
+    }
+
+    /// Gets where a user stands in a guild, or None if they have never been seen there
+    #[instrument(skip(self))]
+    pub async fn get_user_rank(
+        &self,
+        guild: GuildId,
+        user: &User,
+        rank_by: RankBy,
+    ) -> sqlx::Result<Option<i64>> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+        let user_id = user_to_id(user);
+
+        // Rank is one more than the number of members that are strictly ahead
+        match rank_by {
+            RankBy::Exp => {
+                sqlx::query_scalar!(
+                    r#"
+                    SELECT (SELECT COUNT(*) FROM members o WHERE o.guild_id = m.guild_id AND o.exp > m.exp) + 1 AS "rank!: i64"
+                    FROM members m WHERE m.guild_id = $1 AND m.user_id = $2
+                    "#,
+                    guild_id,
+                    user_id
+                )
+                .fetch_optional(&pool)
+                .await
+            }
+            RankBy::VBucks => {
+                sqlx::query_scalar!(
+                    r#"
+                    SELECT (SELECT COUNT(*) FROM members o WHERE o.guild_id = m.guild_id AND o.vbucks > m.vbucks) + 1 AS "rank!: i64"
+                    FROM members m WHERE m.guild_id = $1 AND m.user_id = $2
+                    "#,
+                    guild_id,
+                    user_id
+                )
+                .fetch_optional(&pool)
+                .await
+            }
+        }
+    }
+
+    /// Gets everyone that has ever been seen in a guild
+    #[instrument(skip(self))]
+    pub async fn get_members(&self, guild: GuildId) -> sqlx::Result<Vec<DbUser>> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+
+        sqlx::query_as!(
+            DbUser,
+            "SELECT u.name, u.id, m.guild_id, m.vbucks, m.exp FROM members m JOIN users u ON u.id = m.user_id WHERE m.guild_id = $1",
+            guild_id
+        )
+        .fetch_all(&pool)
+        .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_member_count(&self, guild: GuildId) -> sqlx::Result<i64> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!: i64" FROM members WHERE guild_id = $1"#,
+            guild_id
+        )
+        .fetch_one(&pool)
+        .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_server_conf(&self, guild: GuildId) -> sqlx::Result<ServerConfig> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+
+        let conf = sqlx::query_as!(
+            ServerConfig,
+            "SELECT * FROM server_config WHERE id = $1",
+            guild_id
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        Ok(conf)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn save_welcome_role(&self, guild: GuildId, role: RoleId) -> sqlx::Result<()> {
+        let pool = self.ctx.get_conn().await;
+        let role_id: u64 = role.into();
+        let role_id = role_id as i64;
+        let guild_id = guild_to_id(guild);
+
+        // upsert
+        sqlx::query!(
+            "
+        INSERT INTO server_config(id, welcome_role_id) 
+        VALUES ($1, $2) 
+        ON CONFLICT(id) 
+        DO 
+            UPDATE SET welcome_role_id = $2",
+            guild_id,
+            role_id
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn save_error_channel(&self, guild: GuildId, channel: ChannelId) -> sqlx::Result<()> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+        let channel_id: i64 = channel.into();
+
+        sqlx::query!(
+            "
+            INSERT INTO server_config(id, error_channel_id)
+            VALUES ($1, $2)
+            ON CONFLICT(id)
+            DO
+                UPDATE SET error_channel_id = $2
+            ",
+            guild_id,
+            channel_id
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn save_economy_admin_role(
+        &self,
+        guild: GuildId,
+        role: Option<RoleId>,
+    ) -> sqlx::Result<()> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+        let role_id = role.map(|r| r.get() as i64);
+
+        sqlx::query!(
+            "
+            INSERT INTO server_config(id, economy_admin_role_id)
+            VALUES ($1, $2)
+            ON CONFLICT(id)
+            DO
+                UPDATE SET economy_admin_role_id = $2
+            ",
+            guild_id,
+            role_id
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn save_audit_channel(
+        &self,
+        guild: GuildId,
+        channel: Option<ChannelId>,
+    ) -> sqlx::Result<()> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+        let channel_id = channel.map(|c| c.get() as i64);
+
+        sqlx::query!(
+            "
+            INSERT INTO server_config(id, audit_channel_id)
+            VALUES ($1, $2)
+            ON CONFLICT(id)
+            DO
+                UPDATE SET audit_channel_id = $2
+            ",
+            guild_id,
+            channel_id
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Get every keyword response for a guild along with its row id, in the order they were added
+    #[instrument(skip(self))]
+    pub async fn get_keyword_responses(
+        &self,
+        guild: GuildId,
+    ) -> sqlx::Result<Vec<(i64, KeywordRule)>> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+
+        sqlx::query!(
+            "SELECT id, keywords, responses, weights, cooldown FROM keyword_responses WHERE guild_id = $1 ORDER BY id",
+            guild_id
+        )
+        .fetch_all(&pool)
+        .await?
+        .into_iter()
+        .map(|r| {
+            Ok((
+                r.id,
+                KeywordRule {
+                    kwr: KeywordResponse::from_parts(
+                        decode_json(&r.keywords)?,
+                        decode_json(&r.responses)?,
+                        r.weights.as_deref().map(decode_json).transpose()?,
+                    ),
+                    cooldown: r.cooldown.map(|c| c as u64),
+                },
+            ))
+        })
+        .collect()
+    }
+
+    /// Adds a keyword response to a guild, returning its new row id
+    #[instrument(skip(self))]
+    pub async fn add_keyword_response(
+        &self,
+        guild: GuildId,
+        rule: &KeywordRule,
+    ) -> sqlx::Result<i64> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+        let (keywords, responses, weights) = encode_keyword_response(&rule.kwr);
+        let cooldown = rule.cooldown.map(|c| c as i64);
+
+        let res = sqlx::query!(
+            "INSERT INTO keyword_responses(guild_id, keywords, responses, weights, cooldown) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+            guild_id,
+            keywords,
+            responses,
+            weights,
+            cooldown
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        Ok(res.id)
+    }
+
+    /// Replaces a guild's keyword response. Returns false if there was no response with that id.
+    #[instrument(skip(self))]
+    pub async fn update_keyword_response(
+        &self,
+        guild: GuildId,
+        id: i64,
+        rule: &KeywordRule,
+    ) -> sqlx::Result<bool> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+        let (keywords, responses, weights) = encode_keyword_response(&rule.kwr);
+        let cooldown = rule.cooldown.map(|c| c as i64);
+
+        let res = sqlx::query!(
+            "UPDATE keyword_responses SET keywords = $1, responses = $2, weights = $3, cooldown = $4 WHERE id = $5 AND guild_id = $6",
+            keywords,
+            responses,
+            weights,
+            cooldown,
+            id,
+            guild_id
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Removes a guild's keyword response. Returns false if there was no response with that id.
+    #[instrument(skip(self))]
+    pub async fn remove_keyword_response(&self, guild: GuildId, id: i64) -> sqlx::Result<bool> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+
+        let res = sqlx::query!(
+            "DELETE FROM keyword_responses WHERE id = $1 AND guild_id = $2",
+            id,
+            guild_id
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Gives a guild the default keyword responses the first time it's seen. Does nothing if the
+    /// guild has already been seeded, even if they have since removed every keyword.
+    #[instrument(skip(self, seed))]
+    pub async fn seed_keyword_responses(
+        &self,
+        guild: GuildId,
+        seed: &[KeywordRule],
+    ) -> sqlx::Result<()> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+        let mut trans = pool.begin().await?;
+
+        let seeded = sqlx::query!(
+            "SELECT keywords_seeded FROM server_config WHERE id = $1",
+            guild_id
+        )
+        .fetch_optional(&mut *trans)
+        .await?
+        .is_some_and(|r| r.keywords_seeded);
+
+        if seeded {
+            return Ok(());
+        }
+
+        for rule in seed {
+            let (keywords, responses, weights) = encode_keyword_response(&rule.kwr);
+            let cooldown = rule.cooldown.map(|c| c as i64);
+
+            sqlx::query!(
+                "INSERT INTO keyword_responses(guild_id, keywords, responses, weights, cooldown) VALUES ($1, $2, $3, $4, $5)",
+                guild_id,
+                keywords,
+                responses,
+                weights,
+                cooldown
+            )
+            .execute(&mut *trans)
+            .await?;
+        }
+
+        sqlx::query!(
+            "
+            INSERT INTO server_config(id, keywords_seeded)
+            VALUES ($1, true)
+            ON CONFLICT(id)
+            DO
+                UPDATE SET keywords_seeded = true
+            ",
+            guild_id
+        )
+        .execute(&mut *trans)
+        .await?;
+
+        trans.commit().await?;
+
+        info!("Seeded {} keyword responses", seed.len());
+
+        Ok(())
+    }
+
+    /// Saves how often keyword and dad bot responses can go off in a guild. None means no limit.
+    #[instrument(skip(self))]
+    pub async fn save_keyword_limits(
+        &self,
+        guild: GuildId,
+        channel_limit: Option<i64>,
+        user_limit: Option<i64>,
+        window: Option<i64>,
+        dad_bot_cooldown: Option<i64>,
+    ) -> sqlx::Result<()> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+
+        sqlx::query!(
+            "
+            INSERT INTO server_config(id, kw_channel_limit, kw_user_limit, kw_limit_window, dad_bot_cooldown)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT(id)
+            DO
+                UPDATE SET kw_channel_limit = $2, kw_user_limit = $3, kw_limit_window = $4, dad_bot_cooldown = $5
+            ",
+            guild_id,
+            channel_limit,
+            user_limit,
+            window,
+            dad_bot_cooldown
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Saves the rules for rewarding messages. Rules that are None are left as they are.
+    #[instrument(skip(self))]
+    pub async fn save_xp_rules(
+        &self,
+        guild: GuildId,
+        cooldown: Option<i64>,
+        min_length: Option<i64>,
+    ) -> sqlx::Result<()> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+
+        sqlx::query!(
+            "
+            INSERT INTO server_config(id, xp_cooldown, xp_min_length)
+            VALUES ($1, $2, $3)
+            ON CONFLICT(id)
+            DO
+                UPDATE SET xp_cooldown = COALESCE($2, xp_cooldown), xp_min_length = COALESCE($3, xp_min_length)
+            ",
+            guild_id,
+            cooldown,
+            min_length
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn save_house_config(
+        &self,
+        guild: GuildId,
+        bankroll: Option<i64>,
+        edge: Option<i64>,
+        adaptive: Option<bool>,
+    ) -> sqlx::Result<()> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+
+        sqlx::query!(
+            "
+            INSERT INTO server_config(id, house_bankroll, house_edge, rps_adaptive)
+            VALUES ($1, COALESCE($2, 0), $3, COALESCE($4, false))
+            ON CONFLICT(id)
+            DO
+                UPDATE SET house_bankroll = COALESCE($2, house_bankroll), house_edge = COALESCE($3, house_edge), rps_adaptive = COALESCE($4, rps_adaptive)
+            ",
+            guild_id,
+            bankroll,
+            edge,
+            adaptive
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Starts holding V-Bucks for a game, which get added with hold_in_escrow. Returns the
+    /// escrow's id.
+    #[instrument(skip(self))]
+    pub async fn open_escrow(&self, guild: GuildId, game: &str) -> sqlx::Result<i64> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+
+        sqlx::query_scalar!(
+            "INSERT INTO escrows(guild_id, game) VALUES ($1, $2) RETURNING id",
+            guild_id,
+            game
+        )
+        .fetch_one(&pool)
+        .await
+    }
+
+    /// Takes V-Bucks from a member and holds them until the game is over. Nothing is taken if
+    /// they can't afford it.
+    #[instrument(skip(self))]
+    pub async fn hold_in_escrow(
+        &self,
+        escrow: i64,
+        guild: GuildId,
+        user: &User,
+        amount: i64,
+        reason: LedgerReason,
+    ) -> Result<(), TransferError> {
+        if amount < 0 {
+            return Err(TransferError::NegativeAmount(amount));
+        }
+
+        let pool = self.ctx.get_conn().await;
+        let mut trans = pool.begin().await?;
+
+        let user_id = user_to_id(user);
+        let guild_id = guild_to_id(guild);
 
-impl<'context> Database<'context> {
-    pub fn new(ctx: impl Into<ContextType<'context>>) -> Database<'context> {
-        Database {
-            ctx: ContextWrapper::new(ctx),
+        let debit = sqlx::query!(
+            "UPDATE members SET vbucks = vbucks - $1 WHERE guild_id = $2 AND user_id = $3 AND vbucks >= $1",
+            amount,
+            guild_id,
+            user_id
+        )
+        .execute(&mut *trans)
+        .await?;
+
+        if debit.rows_affected() == 0 {
+            let balance = sqlx::query_scalar!(
+                "SELECT vbucks FROM members WHERE guild_id = $1 AND user_id = $2",
+                guild_id,
+                user_id
+            )
+            .fetch_optional(&mut *trans)
+            .await?;
+
+            return Err(match balance {
+                Some(balance) => TransferError::InsufficientFunds {
+                    balance,
+                    needed: amount,
+                },
+                None => TransferError::UnknownMember(user.id),
+            });
         }
+
+        sqlx::query!(
+            "
+            INSERT INTO escrow_holds(escrow_id, user_id, amount)
+            VALUES ($1, $2, $3)
+            ON CONFLICT(escrow_id, user_id)
+            DO
+                UPDATE SET amount = amount + $3
+            ",
+            escrow,
+            user_id,
+            amount
+        )
+        .execute(&mut *trans)
+        .await?;
+
+        record_ledger(&mut trans, guild_id, user_id, -amount, reason, None).await?;
+
+        trans.commit().await?;
+
+        Ok(())
     }
 
-    // Creates a user, ignores if user already exists
+    /// Pays everything held in an escrow to the winner and closes it. Returns how much they got.
     #[instrument(skip(self))]
-    pub async fn create_user(&self, user: &User) -> sqlx::Result<DbUser> {
+    pub async fn release_escrow(
+        &self,
+        escrow: i64,
+        winner: &User,
+        reason: LedgerReason,
+    ) -> sqlx::Result<i64> {
         let pool = self.ctx.get_conn().await;
-        let user_id: u64 = user.id.into();
-        let user_id = user_id as i64;
+        let mut trans = pool.begin().await?;
+        let user_id = user_to_id(winner);
 
-        let db_user = sqlx::query_as!(DbUser,
-            "INSERT or IGNORE into users (name, id, vbucks, exp) values($1, $2, $3, $4) returning *",
-            user.name,
-            user_id,
-            0,
-            0
+        let guild_id = sqlx::query_scalar!("SELECT guild_id FROM escrows WHERE id = $1", escrow)
+            .fetch_one(&mut *trans)
+            .await?;
+        let pot = sqlx::query_scalar!(
+            r#"SELECT COALESCE(SUM(amount), 0) AS "pot!: i64" FROM escrow_holds WHERE escrow_id = $1"#,
+            escrow
         )
-        .fetch_one(&pool)
+        .fetch_one(&mut *trans)
         .await?;
 
-        info!("Created user {:?}", db_user);
+        sqlx::query!(
+            "UPDATE members SET vbucks = vbucks + $1 WHERE guild_id = $2 AND user_id = $3",
+            pot,
+            guild_id,
+            user_id
+        )
+        .execute(&mut *trans)
+        .await?;
 
-        Ok(db_user)
+        record_ledger(&mut trans, guild_id, user_id, pot, reason, None).await?;
+        close_escrow(&mut trans, escrow).await?;
+
+        trans.commit().await?;
+
+        Ok(pot)
     }
 
-    // Gets a user from db, creates one if they don't exist
+    /// Gives everyone in an escrow back what they put in and closes it
     #[instrument(skip(self))]
-    pub async fn get_user(&self, user: &User) -> sqlx::Result<DbUser> {
+    pub async fn refund_escrow(&self, escrow: i64) -> sqlx::Result<()> {
         let pool = self.ctx.get_conn().await;
-        let user_id = user_to_id(user);
+        let mut trans = pool.begin().await?;
 
-        let db_user = sqlx::query_as!(DbUser, "SELECT * FROM users WHERE id = $1", user_id)
-            .fetch_optional(&pool)
-            .await?;
+        refund_escrow_in(&mut trans, escrow).await?;
 
-        match db_user {
-            Some(x) => {
-                info!("Got db user: {:?}", x);
-                Ok(x)
-            }
-            None => self.create_user(user).await,
-        }
+        trans.commit().await
     }
 
+    /// Settles a game a member played against Johnson and closes its escrow. A positive net is
+    /// what they won on top of their held stake and comes out of the house bankroll, a negative
+    /// one is what they lost from it and goes into the bankroll. Whatever is left of their stake
+    /// goes back to them, and that amount is returned. Nothing changes if the bankroll can't
+    /// cover their winnings.
     #[instrument(skip(self))]
-    pub async fn give_user_money(
+    pub async fn settle_house_escrow(
         &self,
+        escrow: i64,
         user: &User,
-        money: i64,
-    ) -> sqlx::Result<SqliteQueryResult> {
+        house: UserId,
+        net: i64,
+        reason: LedgerReason,
+    ) -> Result<i64, TransferError> {
         let pool = self.ctx.get_conn().await;
+        let mut trans = pool.begin().await?;
+
         let user_id = user_to_id(user);
+        let house_id = house.get() as i64;
 
-        // increment the money amount by "money" param
-        sqlx::query!(
-            "UPDATE users SET vbucks = vbucks + $1 WHERE id = $2",
-            money,
+        let guild_id = sqlx::query_scalar!("SELECT guild_id FROM escrows WHERE id = $1", escrow)
+            .fetch_one(&mut *trans)
+            .await?;
+        let held = sqlx::query_scalar!(
+            "SELECT amount FROM escrow_holds WHERE escrow_id = $1 AND user_id = $2",
+            escrow,
             user_id
         )
-        .execute(&pool)
-        .await
+        .fetch_optional(&mut *trans)
+        .await?
+        .unwrap_or(0);
+
+        // They can't lose more than they put in
+        let net = net.max(-held);
+
+        if net > 0 {
+            let debit = sqlx::query!(
+                "UPDATE server_config SET house_bankroll = house_bankroll - $1 WHERE id = $2 AND house_bankroll >= $1",
+                net,
+                guild_id
+            )
+            .execute(&mut *trans)
+            .await?;
+
+            if debit.rows_affected() == 0 {
+                let bankroll = sqlx::query_scalar!(
+                    "SELECT house_bankroll FROM server_config WHERE id = $1",
+                    guild_id
+                )
+                .fetch_optional(&mut *trans)
+                .await?;
+
+                return Err(TransferError::HouseCantCover {
+                    bankroll: bankroll.unwrap_or(0),
+                    needed: net,
+                });
+            }
+        } else if net < 0 {
+            let lost = -net;
+            sqlx::query!(
+                "
+                INSERT INTO server_config(id, house_bankroll)
+                VALUES ($1, $2)
+                ON CONFLICT(id)
+                DO
+                    UPDATE SET house_bankroll = house_bankroll + $2
+                ",
+                guild_id,
+                lost
+            )
+            .execute(&mut *trans)
+            .await?;
+        }
+
+        let returned = held + net;
+        if returned > 0 {
+            let credit = sqlx::query!(
+                "UPDATE members SET vbucks = vbucks + $1 WHERE guild_id = $2 AND user_id = $3",
+                returned,
+                guild_id,
+                user_id
+            )
+            .execute(&mut *trans)
+            .await?;
+
+            if credit.rows_affected() == 0 {
+                return Err(TransferError::UnknownMember(user.id));
+            }
+
+            record_ledger(
+                &mut trans,
+                guild_id,
+                user_id,
+                returned,
+                reason,
+                Some(house_id),
+            )
+            .await?;
+        }
+
+        close_escrow(&mut trans, escrow).await?;
+
+        trans.commit().await?;
+
+        Ok(returned)
     }
 
+    /// How many times a member has thrown each move against Johnson, by the move's name
     #[instrument(skip(self))]
-    pub async fn give_user_exp(&self, user: &User, exp: i64) -> sqlx::Result<i64> {
+    pub async fn get_rps_moves(
+        &self,
+        guild: GuildId,
+        user: &User,
+    ) -> sqlx::Result<HashMap<String, i64>> {
         let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
         let user_id = user_to_id(user);
 
-        // increment the money amount by "money" param
-        let res = sqlx::query!(
-            "UPDATE users SET exp = exp + $1 WHERE id = $2 RETURNING exp",
-            exp,
+        let moves = sqlx::query!(
+            "SELECT move, count FROM rps_moves WHERE guild_id = $1 AND user_id = $2",
+            guild_id,
             user_id
         )
-        .fetch_one(&pool)
+        .fetch_all(&pool)
         .await?;
 
-        Ok(res.exp)
+        Ok(moves.into_iter().map(|m| (m.r#move, m.count)).collect())
     }
 
     #[instrument(skip(self))]
-    pub async fn user_transaction(
+    pub async fn record_rps_moves(
         &self,
-        from_user: &User,
-        to_user: &User,
-        money: i64,
+        guild: GuildId,
+        user: &User,
+        moves: &[&str],
     ) -> sqlx::Result<()> {
         let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+        let user_id = user_to_id(user);
         let mut trans = pool.begin().await?;
 
-        let from_user_id = user_to_id(from_user);
-        let to_user_id = user_to_id(to_user);
+        for m in moves {
+            sqlx::query!(
+                "
+                INSERT INTO rps_moves(guild_id, user_id, move, count)
+                VALUES ($1, $2, $3, 1)
+                ON CONFLICT(guild_id, user_id, move)
+                DO
+                    UPDATE SET count = count + 1
+                ",
+                guild_id,
+                user_id,
+                m
+            )
+            .execute(&mut *trans)
+            .await?;
+        }
+
+        trans.commit().await?;
+
+        Ok(())
+    }
+
+    /// Gets the XP curve a guild uses, which is the original one unless they picked their own
+    #[instrument(skip(self))]
+    pub async fn get_xp_curve(&self, guild: GuildId) -> sqlx::Result<XpCurve> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+
+        let curve =
+            sqlx::query_scalar!("SELECT xp_curve FROM server_config WHERE id = $1", guild_id)
+                .fetch_optional(&pool)
+                .await?
+                .flatten();
+
+        curve.map_or(Ok(XpCurve::default()), |c| {
+            decode_json(&c).map(XpCurve::usable)
+        })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn save_xp_curve(&self, guild: GuildId, curve: &XpCurve) -> sqlx::Result<()> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+        let curve = serde_json::to_string(curve).expect("XpCurve should serialize");
 
         sqlx::query!(
-            "UPDATE users SET vbucks = vbucks - $1 WHERE id = $2",
-            money,
-            from_user_id
+            "
+            INSERT INTO server_config(id, xp_curve)
+            VALUES ($1, $2)
+            ON CONFLICT(id)
+            DO
+                UPDATE SET xp_curve = $2
+            ",
+            guild_id,
+            curve
         )
-        .execute(&mut *trans)
+        .execute(&pool)
         .await?;
 
+        Ok(())
+    }
+
+    /// Saves the V-Bucks and XP ranges a guild gives out for messages. Ranges are inclusive.
+    #[instrument(skip(self))]
+    pub async fn save_reward_ranges(
+        &self,
+        guild: GuildId,
+        money: RangeInclusive<i64>,
+        exp: RangeInclusive<i64>,
+    ) -> sqlx::Result<()> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+        let (money_min, money_max) = money.into_inner();
+        let (exp_min, exp_max) = exp.into_inner();
+
         sqlx::query!(
-            "UPDATE users SET vbucks = vbucks + $1 WHERE id = $2",
-            money,
-            to_user_id,
+            "
+            INSERT INTO server_config(id, money_min, money_max, exp_min, exp_max)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT(id)
+            DO
+                UPDATE SET money_min = $2, money_max = $3, exp_min = $4, exp_max = $5
+            ",
+            guild_id,
+            money_min,
+            money_max,
+            exp_min,
+            exp_max
         )
-        .execute(&mut *trans)
+        .execute(&pool)
         .await?;
 
         Ok(())
     }
 
+    /// Gets every role a guild gives out for reaching a level, lowest level first
     #[instrument(skip(self))]
-    pub async fn get_server_conf(&self, guild: GuildId) -> sqlx::Result<ServerConfig> {
+    pub async fn get_level_roles(&self, guild: GuildId) -> sqlx::Result<Vec<(i64, RoleId)>> {
         let pool = self.ctx.get_conn().await;
         let guild_id = guild_to_id(guild);
 
-        let conf = sqlx::query_as!(
-            ServerConfig,
-            "SELECT * FROM server_config WHERE id = $1",
+        let level_roles = sqlx::query!(
+            "SELECT level, role_id FROM level_roles WHERE guild_id = $1 ORDER BY level",
             guild_id
         )
-        .fetch_one(&pool)
+        .fetch_all(&pool)
+        .await?
+        .into_iter()
+        .map(|r| (r.level, RoleId::new(r.role_id as u64)))
+        .collect();
+
+        Ok(level_roles)
+    }
+
+    /// Gives out a role for reaching a level. A role can only be given for one level, so adding
+    /// it again moves it.
+    #[instrument(skip(self))]
+    pub async fn save_level_role(
+        &self,
+        guild: GuildId,
+        level: i64,
+        role: RoleId,
+    ) -> sqlx::Result<()> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+        let role_id: i64 = role.into();
+
+        sqlx::query!(
+            "REPLACE INTO level_roles(guild_id, role_id, level) VALUES ($1, $2, $3)",
+            guild_id,
+            role_id,
+            level
+        )
+        .execute(&pool)
         .await?;
 
-        Ok(conf)
+        Ok(())
     }
 
+    /// Returns false if the role wasn't a level role
     #[instrument(skip(self))]
-    pub async fn save_welcome_role(&self, guild: GuildId, role: RoleId) -> sqlx::Result<()> {
+    pub async fn remove_level_role(&self, guild: GuildId, role: RoleId) -> sqlx::Result<bool> {
+        let pool = self.ctx.get_conn().await;
+        let guild_id = guild_to_id(guild);
+        let role_id: i64 = role.into();
+
+        let res = sqlx::query!(
+            "DELETE FROM level_roles WHERE guild_id = $1 AND role_id = $2",
+            guild_id,
+            role_id
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn save_replace_level_roles(
+        &self,
+        guild: GuildId,
+        replace: bool,
+    ) -> sqlx::Result<()> {
         let pool = self.ctx.get_conn().await;
-        let role_id: u64 = role.into();
-        let role_id = role_id as i64;
         let guild_id = guild_to_id(guild);
 
-        // upsert
         sqlx::query!(
             "
-        INSERT INTO server_config(id, welcome_role_id) 
-        VALUES ($1, $2) 
-        ON CONFLICT(id) 
-        DO 
-            UPDATE SET welcome_role_id = $2",
+            INSERT INTO server_config(id, replace_level_roles)
+            VALUES ($1, $2)
+            ON CONFLICT(id)
+            DO
+                UPDATE SET replace_level_roles = $2
+            ",
             guild_id,
-            role_id
+            replace
         )
         .execute(&pool)
         .await?;
@@ -255,22 +1860,35 @@ impl<'context> Database<'context> {
         Ok(())
     }
 
+    /// Saves where level up messages go. The channel and message are left as they are if None.
     #[instrument(skip(self))]
-    pub async fn save_error_channel(&self, guild: GuildId, channel: ChannelId) -> sqlx::Result<()> {
+    pub async fn save_level_up_config(
+        &self,
+        guild: GuildId,
+        mode: LevelUpMode,
+        channel: Option<ChannelId>,
+        message: Option<&str>,
+    ) -> sqlx::Result<()> {
         let pool = self.ctx.get_conn().await;
         let guild_id = guild_to_id(guild);
-        let channel_id: i64 = channel.into();
+        let mode = mode as u8;
+        let channel_id: Option<i64> = channel.map(|c| c.into());
 
         sqlx::query!(
             "
-            INSERT INTO server_config(id, error_channel_id)
-            VALUES ($1, $2)
+            INSERT INTO server_config(id, level_up_mode, level_up_channel_id, level_up_message)
+            VALUES ($1, $2, $3, $4)
             ON CONFLICT(id)
             DO
-                UPDATE SET error_channel_id = $2
+                UPDATE SET
+                    level_up_mode = $2,
+                    level_up_channel_id = COALESCE($3, level_up_channel_id),
+                    level_up_message = COALESCE($4, level_up_message)
             ",
             guild_id,
-            channel_id
+            mode,
+            channel_id,
+            message
         )
         .execute(&pool)
         .await?;
@@ -425,12 +2043,6 @@ impl<'context> Database<'context> {
     }
 }
 
-pub fn exp_to_level(exp: i64) -> i64 {
-    let inside_log: f64 = (exp as f64 + XP_TRANSLATION) / XP_MULTIPLIER;
-
-    ((inside_log.log(E) / EXPO_MULTIPLIER) + 1f64) as i64
-}
-
 // weird shit to get around the fact that SQLite doesn't support u64 but discord stores their
 // ids as u64
 fn user_to_id(user: &User) -> i64 {
@@ -442,3 +2054,133 @@ fn guild_to_id(guild: GuildId) -> i64 {
     let guild_id: u64 = guild.into();
     guild_id as i64
 }
+
+// keyword responses are stored as json arrays of their keywords, responses and weights
+fn encode_keyword_response(kwr: &KeywordResponse) -> (String, String, Option<String>) {
+    (
+        serde_json::to_string(kwr.keywords()).expect("Vec<String> should serialize"),
+        serde_json::to_string(kwr.responses()).expect("Vec<String> should serialize"),
+        kwr.weights()
+            .map(|w| serde_json::to_string(w).expect("Vec<f32> should serialize")),
+    )
+}
+
+/// Refunds every escrow that is still open. Games only live in memory, so at startup any open
+/// escrow belongs to a game that was cut off by a crash or restart. Returns how many were
+/// refunded.
+#[instrument(skip(pool))]
+pub async fn refund_orphaned_escrows(pool: &SqlitePool) -> sqlx::Result<usize> {
+    let escrows = sqlx::query_scalar!("SELECT id FROM escrows")
+        .fetch_all(pool)
+        .await?;
+
+    for escrow in &escrows {
+        let mut trans = pool.begin().await?;
+        refund_escrow_in(&mut trans, *escrow).await?;
+        trans.commit().await?;
+    }
+
+    Ok(escrows.len())
+}
+
+async fn refund_escrow_in(conn: &mut SqliteConnection, escrow: i64) -> sqlx::Result<()> {
+    let guild_id = sqlx::query_scalar!("SELECT guild_id FROM escrows WHERE id = $1", escrow)
+        .fetch_one(&mut *conn)
+        .await?;
+    let holds = sqlx::query!(
+        "SELECT user_id, amount FROM escrow_holds WHERE escrow_id = $1",
+        escrow
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    for hold in holds {
+        sqlx::query!(
+            "UPDATE members SET vbucks = vbucks + $1 WHERE guild_id = $2 AND user_id = $3",
+            hold.amount,
+            guild_id,
+            hold.user_id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        record_ledger(
+            conn,
+            guild_id,
+            hold.user_id,
+            hold.amount,
+            LedgerReason::EscrowRefund,
+            None,
+        )
+        .await?;
+    }
+
+    info!(escrow, "Refunded escrow");
+    close_escrow(conn, escrow).await
+}
+
+async fn close_escrow(conn: &mut SqliteConnection, escrow: i64) -> sqlx::Result<()> {
+    sqlx::query!("DELETE FROM escrow_holds WHERE escrow_id = $1", escrow)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM escrows WHERE id = $1", escrow)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+// Every change to a member's V-Bucks or XP has to go through one of these so /history adds up
+async fn record_ledger(
+    conn: &mut SqliteConnection,
+    guild_id: i64,
+    user_id: i64,
+    amount: i64,
+    reason: LedgerReason,
+    counterparty: Option<i64>,
+) -> sqlx::Result<()> {
+    record_ledger_in(
+        conn,
+        guild_id,
+        user_id,
+        Currency::VBucks,
+        amount,
+        reason,
+        counterparty,
+    )
+    .await
+}
+
+async fn record_ledger_in(
+    conn: &mut SqliteConnection,
+    guild_id: i64,
+    user_id: i64,
+    currency: Currency,
+    amount: i64,
+    reason: LedgerReason,
+    counterparty: Option<i64>,
+) -> sqlx::Result<()> {
+    let reason = reason.as_str();
+    let currency = currency.as_str();
+
+    sqlx::query!(
+        "
+        INSERT INTO ledger(guild_id, user_id, amount, reason, counterparty, currency)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ",
+        guild_id,
+        user_id,
+        amount,
+        reason,
+        counterparty,
+        currency
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+fn decode_json<T: serde::de::DeserializeOwned>(json: &str) -> sqlx::Result<T> {
+    serde_json::from_str(json).map_err(|err| sqlx::Error::Decode(Box::new(err)))
+}
@@ -1,8 +1,8 @@
-#![allow(clippy::derived_hash_with_manual_eq)]
-
 use std::env;
 use std::fmt::Display;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::io::Write;
+use std::ops::RangeInclusive;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -13,29 +13,35 @@ use poise::serenity_prelude::{
 };
 use poise::{CreateReply, FrameworkContext, FrameworkError};
 
+use once_cell::sync::Lazy;
 use problemo::*;
 use rand::Rng;
 use rand::distr::Distribution;
 use rand::distr::weighted::WeightedIndex;
 use regex::Regex;
 use tokio::sync::RwLock;
-use tracing::{Instrument, debug, error, info, info_span, instrument};
+use tracing::{Instrument, debug, error, info, info_span, instrument, warn};
 
 use crate::checks::slurs;
 use crate::custom_types::command::{Data, Error, KeywordResponse, SerenityCtxData};
-use crate::db::{self, Database};
+use crate::custom_types::mongo_schema::ServerConfig;
+use crate::db::{Database, LedgerReason};
+use crate::keywords::GuildKeywords;
+use crate::levels::{self, RewardRanges, XpCurve};
+use crate::rng::RngProvider;
+use crate::throttle::{ResponseLimits, ResponseSource, ResponseThrottle};
 use crate::utils::message;
 
-const MONEY_MIN: i64 = 5;
-const MONEY_MAX: i64 = 20;
-
-const EXP_PER_MESSAGE: i64 = 100;
+// Defaults for guilds that haven't set their own reward rules
+const DEFAULT_XP_COOLDOWN: i64 = 60;
+const DEFAULT_XP_MIN_LENGTH: usize = 2;
 
 const MESSAGE_TIME: Duration = Duration::from_mins(30);
 const MESSAGE_CHANCE: f64 = 0.01;
 
 gloss_error!(NewGuildMemberError, "Error processing new guild member");
 static_gloss_error!(RewardError, "Error while trying to give user rewards");
+static_gloss_error!(KeywordLoadError, "Error while loading keyword responses");
 static_gloss_error!(DadBotError, "Error while trying to make funny dad joke");
 static_gloss_error!(
     FriendMessageError,
@@ -44,6 +50,31 @@ static_gloss_error!(
 
 attachment!(GuildIdAttachment, GuildId);
 
+static CUSTOM_EMOJI_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"<a?:\w+:\d+>").expect("Invalid regex pattern"));
+
+/// A guild's rules on which messages are worth rewarding
+#[derive(Debug)]
+struct RewardRules {
+    // Seconds a user has to wait between rewarded messages
+    cooldown: i64,
+    // Shortest message (in characters) that gets rewarded
+    min_length: usize,
+}
+
+impl RewardRules {
+    fn from_conf(conf: Option<&ServerConfig>) -> RewardRules {
+        RewardRules {
+            cooldown: conf
+                .and_then(|c| c.xp_cooldown)
+                .unwrap_or(DEFAULT_XP_COOLDOWN),
+            min_length: conf
+                .and_then(|c| c.xp_min_length)
+                .map_or(DEFAULT_XP_MIN_LENGTH, |l| l as usize),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct KeywordError {
     keyword: KeywordResponse,
@@ -179,43 +210,88 @@ fn create_pretty_error_string(problem: &Error) -> String {
     String::from_utf8(error_buf).expect("Error message is valid utf8")
 }
 
-#[instrument(skip(ctx, message), fields(message=message.content))]
+#[instrument(skip(ctx, message, rng), fields(message=message.content))]
 async fn reward_messenger(
     guild_id: GuildId,
     ctx: &Context,
     message: &Message,
+    rng: &RngProvider,
 ) -> Result<(), Problem> {
     let db_helper = Database::new(ctx);
 
+    let server_conf = match db_helper.get_server_conf(guild_id).await {
+        Ok(conf) => Some(conf),
+        Err(sqlx::Error::RowNotFound) => None,
+        Err(err) => {
+            return Err(RewardError::as_problem("Couldn't get server config")
+                .via(err)
+                .with(GuildIdAttachment::new(guild_id)));
+        }
+    };
+    let rules = RewardRules::from_conf(server_conf.as_ref());
+    let ranges = RewardRanges::from_conf(server_conf.as_ref());
+    let curve = XpCurve::from_conf(server_conf.as_ref())
+        .via(RewardError::new("Guild has an invalid XP curve"))
+        .with(GuildIdAttachment::new(guild_id))?;
+
+    if !worth_rewarding(&message.content, &rules) {
+        debug!(rules = ?rules, "Message isn't worth rewarding");
+        return Ok(());
+    }
+
     // we're fine to do this before the give_user_money call later because we won't use
     // this older money value
     let db_user = db_helper
-        .get_user(&message.author)
+        .get_user(guild_id, &message.author)
         .await
         .via(RewardError::new("Couldn't get user in database"))
         .with(GuildIdAttachment::new(guild_id))?;
 
-    let reward_amount = money_rand();
+    // Also makes sure that repeating the same message over and over doesn't do anything
+    let claimed = db_helper
+        .claim_message_reward(
+            guild_id,
+            &message.author,
+            message_hash(&message.content),
+            rules.cooldown,
+        )
+        .await
+        .via(RewardError::new("Couldn't check user's reward cooldown"))
+        .with(GuildIdAttachment::new(guild_id))?;
+
+    if !claimed {
+        debug!("User is on reward cooldown or repeated themselves");
+        return Ok(());
+    }
+
+    let (reward_amount, exp_amount) = rng.with(|rng| {
+        (
+            reward_rand(rng, &ranges.money),
+            reward_rand(rng, &ranges.exp),
+        )
+    });
 
     db_helper
-        .give_user_money(&message.author, reward_amount)
+        .give_user_money(
+            guild_id,
+            &message.author,
+            reward_amount,
+            LedgerReason::MessageReward,
+        )
         .await
         .via(RewardError::new("Couldn't give user money for message"))
         .with(GuildIdAttachment::new(guild_id))?;
 
-    let prev_level = db::exp_to_level(db_user.exp);
+    let prev_level = curve.level(db_user.exp);
 
     let res = db_helper
-        .give_user_exp(&message.author, EXP_PER_MESSAGE)
+        .give_user_exp(guild_id, &message.author, exp_amount)
         .await
         .via(RewardError::new("Could not give user exp for message"))
         .with(GuildIdAttachment::new(guild_id))?;
 
-    let new_level = db::exp_to_level(res);
-    info!(
-        exp = EXP_PER_MESSAGE,
-        reward_amount, "Gave user exp and money"
-    );
+    let new_level = curve.level(res);
+    info!(exp_amount, reward_amount, "Gave user exp and money");
 
     if new_level > prev_level {
         debug!(
@@ -225,26 +301,46 @@ async fn reward_messenger(
             new_level
         );
 
-        message
-            .reply_mention(
-                &ctx,
-                format!("You leveled up from {prev_level} to {new_level}!"),
-            )
+        // Closed DMs or a deleted channel shouldn't stop them from getting their level roles
+        if let Err(err) = levels::announce_level_up(
+            ctx,
+            server_conf.as_ref(),
+            message,
+            prev_level,
+            new_level,
+            db_user.vbucks + reward_amount,
+        )
+        .await
+        {
+            warn!(
+                "Couldn't announce level up for {}: {err}",
+                message.author.name
+            );
+        }
+
+        let member = guild_id
+            .member(ctx, message.author.id)
             .await
             .via(RewardError::new(
-                "Error when trying to send level up message",
+                "Couldn't get member to give level roles to",
             ))
             .with(GuildIdAttachment::new(guild_id))?;
+
+        levels::sync_level_roles(&ctx.http, &db_helper, &member, new_level)
+            .await
+            .with(GuildIdAttachment::new(guild_id))?;
     }
 
     Ok(())
 }
 
-#[instrument(skip(ctx, message), fields(message = message.content))]
+#[instrument(skip(ctx, message, throttle), fields(message = message.content))]
 async fn dad_bot_response(
     guild_id: GuildId,
     ctx: &Context,
     message: &Message,
+    throttle: &ResponseThrottle,
+    limits: &ResponseLimits,
 ) -> Result<(), Problem> {
     let message_content = message.content_safe(ctx).to_lowercase();
 
@@ -256,6 +352,18 @@ async fn dad_bot_response(
     let caps = re.captures(&message_content);
 
     if let Some(mat) = caps {
+        if !throttle.try_respond(
+            guild_id,
+            message.channel_id,
+            message.author.id,
+            ResponseSource::DadBot,
+            limits.dad_bot_cooldown,
+            limits,
+        ) {
+            debug!("Dad bot response throttled");
+            return Ok(());
+        }
+
         let im_match = mat.name("im").unwrap();
 
         #[allow(unused_assignments)]
@@ -286,137 +394,46 @@ async fn dad_bot_response(
     Ok(())
 }
 
-#[instrument(skip(ctx, message, kwrs), fields(message = message.content))]
+#[instrument(skip(ctx, message, keywords, throttle, rng), fields(message = message.content))]
 async fn keyword_response(
     guild_id: GuildId,
     ctx: &Context,
     message: &Message,
-    kwrs: &[KeywordResponse],
+    keywords: &GuildKeywords,
+    throttle: &ResponseThrottle,
+    rng: &RngProvider,
 ) -> Result<(), Problem> {
-    for kwr in kwrs {
-        match kwr {
-            KeywordResponse::SingleKW { kw, response } => {
-                // no way to avoid recompiling the regex for every keyword
-                let kw_re = single_keyword_regex(kw);
-                // let pos_isolated_word = message.content_safe(ctx).find(&format!(" {} ", kwr.kw));
-                // let pos_final_word = message.content_safe(ctx).find(&format!(" {}", kwr.kw));
-                //
-
-                // if pos_isolated_word.is_some() || pos_final_word.is_some() {
-                if kw_re.is_match(&message.content_safe(ctx).to_lowercase()) {
-                    let message = message
-                        .reply(ctx, response)
-                        .await
-                        .via(KeywordError {
-                            keyword: kwr.to_owned(),
-                        })
-                        .with(GuildIdAttachment::new(guild_id))?;
-
-                    info!(
-                        "Johnson Bot replied to keyword {}, with {}",
-                        kw, message.content
-                    )
-                }
-            }
-            KeywordResponse::MultiKW { kws, response } => {
-                let kw_re = multi_keyword_regex(kws);
-
-                if kw_re.is_match(&message.content_safe(ctx).to_lowercase()) {
-                    let message = message
-                        .reply(ctx, response)
-                        .await
-                        .via(KeywordError {
-                            keyword: kwr.to_owned(),
-                        })
-                        .with(GuildIdAttachment::new(guild_id))?;
-
-                    info!(
-                        "Johnson Bot replied to multi keyword {:?}, with {}",
-                        kws, message.content
-                    );
-                }
-            }
-            KeywordResponse::MultiResponse { kw, responses } => {
-                let kw_re = single_keyword_regex(kw);
-
-                if kw_re.is_match(&message.content_safe(ctx).to_lowercase()) {
-                    let message = message
-                        .reply(ctx, random_choice_unweighted(responses))
-                        .await
-                        .via(KeywordError {
-                            keyword: kwr.to_owned(),
-                        })
-                        .with(GuildIdAttachment::new(guild_id))?;
-
-                    info!(
-                        "Johnson Bot replied to multi response keyword {}, with {}",
-                        kw, message.content
-                    );
-                }
-            }
-            KeywordResponse::WeightedResponses {
-                kw,
-                responses,
-                weights,
-            } => {
-                let kw_re = single_keyword_regex(kw);
+    let content = message.content_safe(ctx);
+
+    for (id, rule) in keywords.matcher.matches(&content) {
+        if !throttle.try_respond(
+            guild_id,
+            message.channel_id,
+            message.author.id,
+            ResponseSource::Keyword(*id),
+            rule.cooldown.map(Duration::from_secs),
+            &keywords.limits,
+        ) {
+            debug!(id, "Keyword response throttled");
+            continue;
+        }
 
-                if kw_re.is_match(&message.content_safe(ctx).to_lowercase()) {
-                    let message = message
-                        .reply(ctx, random_choice_weighted(responses, weights))
-                        .await
-                        .via(KeywordError {
-                            keyword: kwr.to_owned(),
-                        })
-                        .with(GuildIdAttachment::new(guild_id))?;
-
-                    info!(
-                        "Johnson Bot replied to weighted response keyword {}, with {}",
-                        kw, message.content
-                    );
-                }
-            }
-            KeywordResponse::MultiKWResponse { kws, responses } => {
-                let kw_re = multi_keyword_regex(kws);
+        let kwr = &rule.kwr;
+        let response = rng.with(|rng| pick_response(rng, kwr));
 
-                if kw_re.is_match(&message.content_safe(ctx).to_lowercase()) {
-                    let message = message
-                        .reply(ctx, random_choice_unweighted(responses))
-                        .await
-                        .via(KeywordError {
-                            keyword: kwr.to_owned(),
-                        })
-                        .with(GuildIdAttachment::new(guild_id))?;
-
-                    info!(
-                        "Johnson Bot replied to multi response keywords: {:?}, with {}",
-                        kws, message.content
-                    );
-                }
-            }
-            KeywordResponse::MultiKWWeightedResponses {
-                kws,
-                responses,
-                weights,
-            } => {
-                let kw_re = multi_keyword_regex(kws);
+        let message = message
+            .reply(ctx, response)
+            .await
+            .via(KeywordError {
+                keyword: kwr.to_owned(),
+            })
+            .with(GuildIdAttachment::new(guild_id))?;
 
-                if kw_re.is_match(&message.content_safe(ctx).to_lowercase()) {
-                    let message = message
-                        .reply(ctx, random_choice_weighted(responses, weights))
-                        .await
-                        .via(KeywordError {
-                            keyword: kwr.to_owned(),
-                        })
-                        .with(GuildIdAttachment::new(guild_id))?;
-
-                    info!(
-                        "Johnson Bot replied to weighted response keywords: {:?}, with {}",
-                        kws, message.content
-                    );
-                }
-            }
-        }
+        info!(
+            "Johnson Bot replied to keywords {:?}, with {}",
+            kwr.keywords(),
+            message.content
+        );
     }
 
     Ok(())
@@ -503,22 +520,41 @@ pub async fn event_handler(
 
                     let mut problems = Problems::default();
 
-
                     // These have the ? at the end but will NOT exit early with an error
                     // give_ok only returns early with the FailFast version of a problems recevier
-                    reward_messenger(guild_id, ctx, new_message)
+                    reward_messenger(guild_id, ctx, new_message, &data.rng)
                         .await
                         .give_ok(&mut problems)?;
 
+                    let keywords = data
+                        .keywords
+                        .get(&Database::new(ctx), guild_id)
+                        .await
+                        .via(KeywordLoadError::new("Couldn't load guild's keyword responses"))
+                        .with(GuildIdAttachment::new(guild_id))
+                        .give_ok(&mut problems)?;
+
                     // Handle result of dad_bot_response
-                    dad_bot_response(guild_id, ctx, new_message)
+                    let limits = keywords
+                        .as_ref()
+                        .map(|k| k.limits.clone())
+                        .unwrap_or_default();
+                    dad_bot_response(guild_id, ctx, new_message, &data.throttle, &limits)
                         .await
                         .give_ok(&mut problems)?;
 
-                    let kw_responses = &data.kwr;
-                    keyword_response(guild_id, ctx, new_message, kw_responses)
+                    if let Some(keywords) = keywords {
+                        keyword_response(
+                            guild_id,
+                            ctx,
+                            new_message,
+                            &keywords,
+                            &data.throttle,
+                            &data.rng,
+                        )
                         .await
                         .give_ok(&mut problems)?;
+                    }
 
                     problems.check()?;
                 }
@@ -608,51 +644,75 @@ pub async fn event_handler(
     }
 }
 
-// Extract out the code for this logic since ThreadRNG is not thread safe
-fn money_rand() -> i64 {
-    let mut rng = rand::rng();
-
-    rng.random_range(MONEY_MIN..MONEY_MAX)
+fn reward_rand(rng: &mut impl Rng, range: &RangeInclusive<i64>) -> i64 {
+    rng.random_range(range.clone())
 }
 
-fn rand_chance(chance: f64) -> bool {
-    let mut rng = rand::rng();
+/// Filters out messages that are too easy to spam: ones that are too short or are only emojis
+fn worth_rewarding(content: &str, rules: &RewardRules) -> bool {
+    let content = content.trim();
 
-    let rand_float = rng.random::<f64>();
-    rand_float < chance
+    if content.chars().count() < rules.min_length {
+        return false;
+    }
+
+    let without_custom = CUSTOM_EMOJI_RE.replace_all(content, "");
+    !without_custom
+        .chars()
+        .all(|c| c.is_whitespace() || is_emoji_char(c))
 }
 
-fn single_keyword_regex(kw: &str) -> Regex {
-    Regex::new(&format!(r"(^|\b)({kw})($|\>)")).unwrap()
+// Close enough to the unicode emoji ranges, including the joiners and modifiers that get stuck
+// between them
+fn is_emoji_char(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1FAFF
+            | 0x2600..=0x27BF
+            | 0x2300..=0x23FF
+            | 0x2B00..=0x2BFF
+            | 0x200D
+            | 0x20E3
+            | 0xFE0F
+            | 0xE0020..=0xE007F
+    )
 }
 
-fn multi_keyword_regex(kws: &[String]) -> Regex {
-    let mut alternate_string = String::new();
+// Only used to compare against the user's last message, so it doesn't matter if it changes
+// between versions
+fn message_hash(content: &str) -> i64 {
+    let mut hasher = DefaultHasher::new();
+    content.trim().to_lowercase().hash(&mut hasher);
+    hasher.finish() as i64
+}
 
-    for i in 0..kws.len() {
-        // Don't put the | symbol on last keyword
-        if i == kws.len() - 1 {
-            alternate_string.push_str(&kws[i]);
-            continue;
-        }
+fn rand_chance(rng: &mut impl Rng, chance: f64) -> bool {
+    let rand_float = rng.random::<f64>();
+    rand_float < chance
+}
 
-        alternate_string.push_str(&format!("{}|", kws[i]))
+fn pick_response<'a>(rng: &mut impl Rng, kwr: &'a KeywordResponse) -> &'a String {
+    match kwr.weights() {
+        Some(weights) => random_choice_weighted(rng, kwr.responses(), weights),
+        None => random_choice_unweighted(rng, kwr.responses()),
     }
-
-    Regex::new(&format!(r"(^|\b)({alternate_string})($|\>)")).unwrap()
 }
 
-fn random_choice_unweighted(responses: &[String]) -> &String {
-    let rand_index = rand::rng().random_range(0..responses.len());
+fn random_choice_unweighted<'a>(rng: &mut impl Rng, responses: &'a [String]) -> &'a String {
+    let rand_index = rng.random_range(0..responses.len());
 
     &responses[rand_index]
 }
 
-fn random_choice_weighted<'a>(responses: &'a [String], weights: &Vec<f32>) -> &'a String {
+fn random_choice_weighted<'a>(
+    rng: &mut impl Rng,
+    responses: &'a [String],
+    weights: &[f32],
+) -> &'a String {
     // Only errors if len of weights is 0
     let weighted_dist = WeightedIndex::new(weights).unwrap();
 
-    &responses[weighted_dist.sample(&mut rand::rng())]
+    &responses[weighted_dist.sample(rng)]
 }
 
 fn get_friend_id() -> Option<UserId> {
@@ -676,18 +736,15 @@ async fn friend_thread(
     friend: &User,
     friend_name: &str,
 ) -> Result<(), Problem> {
-    let friend_online = {
+    let (friend_online, rng) = {
         let data_map = data.read().await;
-        let friend_info = &data_map
-            .get::<SerenityCtxData>()
-            .expect("Invalid ctx data")
-            .friend_info;
+        let data = data_map.get::<SerenityCtxData>().expect("Invalid ctx data");
 
-        friend_info.online()
+        (data.friend_info.online(), data.rng.clone())
     };
 
     if friend_online {
-        if rand_chance(MESSAGE_CHANCE) {
+        if rng.with(|rng| rand_chance(rng, MESSAGE_CHANCE)) {
             let message = friend
                 .direct_message(http, CreateMessage::new().content("i want you"))
                 .await
@@ -722,3 +779,117 @@ async fn friend_thread(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn responses(responses: &[&str]) -> Vec<String> {
+        responses.iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn single_response_always_picked() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let kwr = KeywordResponse::SingleKW {
+            kw: String::from("hello"),
+            response: String::from("hi"),
+        };
+
+        for _ in 0..20 {
+            assert_eq!(pick_response(&mut rng, &kwr), "hi");
+        }
+    }
+
+    #[test]
+    fn unweighted_responses_all_get_picked() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let kwr = KeywordResponse::MultiResponse {
+            kw: String::from("hello"),
+            responses: responses(&["hi", "hey", "yo"]),
+        };
+
+        let picked: Vec<&String> = (0..100).map(|_| pick_response(&mut rng, &kwr)).collect();
+        for response in kwr.responses() {
+            assert!(picked.contains(&response), "{response} was never picked");
+        }
+    }
+
+    #[test]
+    fn zero_weight_never_picked() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let kwr = KeywordResponse::WeightedResponses {
+            kw: String::from("hello"),
+            responses: responses(&["hi", "never", "hey"]),
+            weights: vec![1.0, 0.0, 3.0],
+        };
+
+        for _ in 0..200 {
+            assert_ne!(pick_response(&mut rng, &kwr), "never");
+        }
+    }
+
+    #[test]
+    fn only_weighted_response_picked() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let kwr = KeywordResponse::MultiKWWeightedResponses {
+            kws: responses(&["hello", "howdy"]),
+            responses: responses(&["hi", "hey", "yo"]),
+            weights: vec![0.0, 2.0, 0.0],
+        };
+
+        for _ in 0..100 {
+            assert_eq!(pick_response(&mut rng, &kwr), "hey");
+        }
+    }
+
+    #[test]
+    fn rewards_stay_in_range() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let ranges = RewardRanges::from_conf(None);
+
+        for _ in 0..1000 {
+            assert!(ranges.money.contains(&reward_rand(&mut rng, &ranges.money)));
+            assert!(ranges.exp.contains(&reward_rand(&mut rng, &ranges.exp)));
+        }
+    }
+
+    #[test]
+    fn fixed_reward() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        assert_eq!(reward_rand(&mut rng, &(7..=7)), 7);
+    }
+
+    #[test]
+    fn rewards_hit_both_ends() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let rolled: Vec<i64> = (0..200).map(|_| reward_rand(&mut rng, &(1..=3))).collect();
+
+        assert!(rolled.contains(&1));
+        assert!(rolled.contains(&3));
+    }
+
+    #[test]
+    fn chance_extremes() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            assert!(!rand_chance(&mut rng, 0.0));
+            assert!(rand_chance(&mut rng, 1.0));
+        }
+    }
+
+    #[test]
+    fn friend_message_is_rare() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let sent = (0..100_000)
+            .filter(|_| rand_chance(&mut rng, MESSAGE_CHANCE))
+            .count();
+
+        // Around 1000 are expected, leave plenty of room either side
+        assert!((500..1500).contains(&sent), "sent {sent} messages");
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Arc;
+
+use poise::serenity_prelude::GuildId;
+use problemo::*;
+use regex::{RegexSet, RegexSetBuilder};
+use tokio::sync::RwLock;
+use tracing::{error, info, instrument};
+
+use crate::custom_types::command::KeywordRule;
+use crate::db::Database;
+use crate::throttle::ResponseLimits;
+
+static_gloss_error!(KeywordCompileError, "Error while compiling keywords");
+
+/// A keyword response that failed to load, along with its position in the list it came from
+#[derive(Debug)]
+pub struct InvalidKeywordResponse {
+    pub index: usize,
+    pub reason: String,
+}
+
+impl Display for InvalidKeywordResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "keyword response {}: {}", self.index, self.reason)
+    }
+}
+
+/// Parses and validates a json list of keyword responses. Every entry is checked, so all of the
+/// bad ones are reported at once rather than just the first.
+pub fn parse_keyword_responses(
+    json: &str,
+) -> Result<Vec<KeywordRule>, Vec<InvalidKeywordResponse>> {
+    let entries: Vec<serde_json::Value> = serde_json::from_str(json).map_err(|err| {
+        vec![InvalidKeywordResponse {
+            index: 0,
+            reason: format!("not a json list: {err}"),
+        }]
+    })?;
+
+    let mut kwrs = Vec::with_capacity(entries.len());
+    let mut invalid = Vec::new();
+
+    for (index, entry) in entries.into_iter().enumerate() {
+        match serde_json::from_value::<KeywordRule>(entry) {
+            Ok(rule) => match rule.kwr.validate() {
+                Ok(()) => kwrs.push(rule),
+                Err(reason) => invalid.push(InvalidKeywordResponse {
+                    index,
+                    reason: reason.to_string(),
+                }),
+            },
+            Err(err) => invalid.push(InvalidKeywordResponse {
+                index,
+                reason: err.to_string(),
+            }),
+        }
+    }
+
+    if invalid.is_empty() {
+        Ok(kwrs)
+    } else {
+        Err(invalid)
+    }
+}
+
+/// Every keyword response of a guild, compiled into a single RegexSet so that a message only has
+/// to be scanned once no matter how many keywords there are.
+#[derive(Debug)]
+pub struct KeywordMatcher {
+    // Keyword rules along with their ids
+    kwrs: Vec<(i64, KeywordRule)>,
+    // Pattern i of the set matches any of the keywords of kwrs[i]
+    set: RegexSet,
+}
+
+impl KeywordMatcher {
+    pub fn new(kwrs: Vec<(i64, KeywordRule)>) -> Result<KeywordMatcher, regex::Error> {
+        let set = RegexSetBuilder::new(
+            kwrs.iter()
+                .map(|(_, rule)| keywords_pattern(rule.kwr.keywords())),
+        )
+        .case_insensitive(true)
+        .build()?;
+
+        Ok(KeywordMatcher { kwrs, set })
+    }
+
+    /// Returns every keyword response with a keyword in the content, in the order they were added
+    pub fn matches<'a>(&'a self, content: &str) -> impl Iterator<Item = &'a (i64, KeywordRule)> {
+        self.set
+            .matches(content)
+            .into_iter()
+            .map(|index| &self.kwrs[index])
+    }
+
+    pub fn len(&self) -> usize {
+        self.kwrs.len()
+    }
+}
+
+// Keywords are matched literally, only as whole words. A keyword that starts or ends with
+// punctuation (like "?") can't have a word boundary on that side, so it is left off.
+fn keywords_pattern(kws: &[String]) -> String {
+    kws.iter()
+        .map(|kw| {
+            let start = if kw.starts_with(is_word_char) {
+                r"\b"
+            } else {
+                ""
+            };
+            let end = if kw.ends_with(is_word_char) {
+                r"\b"
+            } else {
+                ""
+            };
+
+            format!("(?:{start}{}{end})", regex::escape(kw))
+        })
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Everything the message handler needs to know about a guild's automatic responses
+#[derive(Debug)]
+pub struct GuildKeywords {
+    pub matcher: KeywordMatcher,
+    pub limits: ResponseLimits,
+}
+
+/// In-memory copy of every guild's compiled keyword responses, so the message handler doesn't
+/// have to hit the database for every message. Guilds are loaded lazily and reloaded whenever
+/// their keywords change.
+#[derive(Debug, Clone)]
+pub struct KeywordCache {
+    // Given to guilds the first time they are loaded
+    seed: Arc<Vec<KeywordRule>>,
+    guilds: Arc<RwLock<HashMap<GuildId, Arc<GuildKeywords>>>>,
+}
+
+impl KeywordCache {
+    pub fn new(seed: Vec<KeywordRule>) -> KeywordCache {
+        KeywordCache {
+            seed: Arc::new(seed),
+            guilds: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Gets a guild's keyword responses, loading them from the database if they aren't cached
+    #[instrument(skip(self, db))]
+    pub async fn get(
+        &self,
+        db: &Database<'_>,
+        guild: GuildId,
+    ) -> Result<Arc<GuildKeywords>, Problem> {
+        if let Some(keywords) = self.guilds.read().await.get(&guild) {
+            return Ok(Arc::clone(keywords));
+        }
+
+        self.reload(db, guild).await
+    }
+
+    /// Reloads and recompiles a guild's keyword responses and limits. Has to be called whenever
+    /// they change.
+    #[instrument(skip(self, db))]
+    pub async fn reload(
+        &self,
+        db: &Database<'_>,
+        guild: GuildId,
+    ) -> Result<Arc<GuildKeywords>, Problem> {
+        db.seed_keyword_responses(guild, &self.seed).await?;
+
+        // Commands validate before saving, so this should only skip rows that were edited by hand
+        let kwrs = db
+            .get_keyword_responses(guild)
+            .await?
+            .into_iter()
+            .filter_map(|(id, rule)| match rule.kwr.validate() {
+                Ok(()) => Some((id, rule)),
+                Err(reason) => {
+                    error!("Skipping invalid keyword response #{id}: {reason}");
+                    None
+                }
+            })
+            .collect();
+
+        let matcher =
+            KeywordMatcher::new(kwrs).via(KeywordCompileError::new("Invalid keyword regex"))?;
+
+        // Seeding always creates the server config, so this should only be missing if someone
+        // deleted it from under us
+        let limits = match db.get_server_conf(guild).await {
+            Ok(conf) => ResponseLimits::from(&conf),
+            Err(sqlx::Error::RowNotFound) => ResponseLimits::default(),
+            Err(err) => return Err(err.into()),
+        };
+
+        info!(limits = ?limits, "Loaded {} keyword responses", matcher.len());
+        let keywords = Arc::new(GuildKeywords { matcher, limits });
+        self.guilds
+            .write()
+            .await
+            .insert(guild, Arc::clone(&keywords));
+
+        Ok(keywords)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_types::command::{KeywordResponse, KeywordResponseError};
+
+    // Each keyword gets its own rule, with its index as the id
+    fn matcher(kws: &[&str]) -> KeywordMatcher {
+        let kwrs = kws
+            .iter()
+            .enumerate()
+            .map(|(id, kw)| {
+                let kwr = KeywordResponse::SingleKW {
+                    kw: kw.to_string(),
+                    response: String::from("response"),
+                };
+                (
+                    id as i64,
+                    KeywordRule {
+                        kwr,
+                        cooldown: None,
+                    },
+                )
+            })
+            .collect();
+
+        KeywordMatcher::new(kwrs).expect("keywords should always compile")
+    }
+
+    fn matched(matcher: &KeywordMatcher, content: &str) -> Vec<i64> {
+        matcher.matches(content).map(|(id, _)| *id).collect()
+    }
+
+    #[test]
+    fn metacharacters_compile() {
+        matcher(&["r&s", "c++", "?", "(", "[a-z]", "a|b", "$", "\\"]);
+    }
+
+    #[test]
+    fn ampersand_is_literal() {
+        let m = matcher(&["r&s"]);
+
+        assert_eq!(matched(&m, "anyone up for r&s"), [0]);
+        assert_eq!(matched(&m, "R&S!"), [0]);
+        assert!(matched(&m, "rs").is_empty());
+        // Both ends are word characters, so both ends need a boundary
+        assert!(matched(&m, "rr&s").is_empty());
+        assert!(matched(&m, "r&ss").is_empty());
+    }
+
+    #[test]
+    fn plus_is_literal() {
+        let m = matcher(&["c++"]);
+
+        assert_eq!(matched(&m, "i love c++"), [0]);
+        assert_eq!(matched(&m, "c++."), [0]);
+        assert!(matched(&m, "ccc").is_empty());
+        assert!(matched(&m, "c+").is_empty());
+        // Starts with a word character so it needs a boundary there
+        assert!(matched(&m, "abc++").is_empty());
+        // Ends with punctuation so there's no boundary to check on that side
+        assert_eq!(matched(&m, "c++17"), [0]);
+    }
+
+    #[test]
+    fn lone_punctuation() {
+        let m = matcher(&["?"]);
+
+        assert_eq!(matched(&m, "what?"), [0]);
+        assert_eq!(matched(&m, "?"), [0]);
+        assert!(matched(&m, "what").is_empty());
+    }
+
+    #[test]
+    fn dot_and_brackets_are_literal() {
+        let m = matcher(&["a.b", "[hi]"]);
+
+        assert_eq!(matched(&m, "a.b"), [0]);
+        assert!(matched(&m, "axb").is_empty());
+        assert_eq!(matched(&m, "say [hi]"), [1]);
+        assert!(matched(&m, "say h").is_empty());
+    }
+
+    #[test]
+    fn words_match_whole() {
+        let m = matcher(&["hi"]);
+
+        assert_eq!(matched(&m, "Hi there"), [0]);
+        assert!(matched(&m, "this").is_empty());
+        assert!(matched(&m, "hit").is_empty());
+    }
+
+    fn invalid_reasons(json: &str) -> Vec<(usize, String)> {
+        parse_keyword_responses(json)
+            .expect_err("json should be rejected")
+            .into_iter()
+            .map(|invalid| (invalid.index, invalid.reason))
+            .collect()
+    }
+
+    #[test]
+    fn every_shape_parses() {
+        let rules = parse_keyword_responses(
+            r#"[
+                {"kw": "hi", "response": "hello"},
+                {"kws": ["hi", "hey"], "response": "hello"},
+                {"kw": "hi", "responses": ["hello", "yo"]},
+                {"kws": ["hi", "hey"], "responses": ["hello", "yo"], "weights": [1, 2]},
+                {"kw": "hi", "response": "hello", "cooldown": 30}
+            ]"#,
+        )
+        .expect("every entry is valid");
+
+        assert_eq!(rules.len(), 5);
+        assert_eq!(rules[4].cooldown, Some(30));
+    }
+
+    #[test]
+    fn regex_syntax_loads_as_a_literal_keyword() {
+        // Keywords are escaped, so a keyword that would be a broken regex is still fine
+        let rules = parse_keyword_responses(r#"[{"kw": "(oops[", "response": "hello"}]"#)
+            .expect("keywords aren't regexes");
+        let m = KeywordMatcher::new(vec![(0, rules[0].clone())]).expect("should compile");
+
+        assert_eq!(matched(&m, "well (oops["), [0]);
+    }
+
+    #[test]
+    fn empty_response_list_is_rejected() {
+        let reasons = invalid_reasons(r#"[{"kw": "hi", "responses": []}]"#);
+
+        assert_eq!(
+            reasons,
+            [(0, KeywordResponseError::NoResponses.to_string())]
+        );
+    }
+
+    #[test]
+    fn malformed_entries_are_all_reported() {
+        let reasons = invalid_reasons(
+            r#"[
+                {"kw": "hi", "response": "hello"},
+                {"kw": "hi", "kws": ["hey"], "response": "hello"},
+                {"kw": "hi"},
+                {"kw": "hi", "response": "hello", "typo": true},
+                {"kw": "hi", "responses": ["a", "b"], "weights": [1]}
+            ]"#,
+        );
+
+        assert_eq!(
+            reasons.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            [1, 2, 3, 4]
+        );
+        assert!(reasons[0].1.contains("both kw and kws"), "{}", reasons[0].1);
+        assert!(reasons[1].1.contains("response"), "{}", reasons[1].1);
+        assert!(reasons[2].1.contains("typo"), "{}", reasons[2].1);
+    }
+
+    #[test]
+    fn not_a_list_is_rejected() {
+        let reasons = invalid_reasons(r#"{"kw": "hi", "response": "hello"}"#);
+
+        assert_eq!(reasons.len(), 1);
+        assert!(reasons[0].1.starts_with("not a json list"));
+    }
+
+    #[test]
+    fn validate_catches_unusable_responses() {
+        let kwr = |kws: &[&str], responses: &[&str], weights: Option<Vec<f32>>| {
+            KeywordResponse::from_parts(
+                kws.iter().map(|kw| kw.to_string()).collect(),
+                responses.iter().map(|r| r.to_string()).collect(),
+                weights,
+            )
+            .validate()
+        };
+
+        assert_eq!(kwr(&["hi"], &["hello"], None), Ok(()));
+        assert_eq!(
+            kwr(&[], &["hello"], None),
+            Err(KeywordResponseError::NoKeywords)
+        );
+        assert_eq!(
+            kwr(&["hi", " "], &["hello"], None),
+            Err(KeywordResponseError::EmptyKeyword)
+        );
+        assert_eq!(
+            kwr(&["hi"], &["hello", ""], None),
+            Err(KeywordResponseError::EmptyResponse)
+        );
+        assert_eq!(
+            kwr(&["hi"], &["a", "b"], Some(vec![1.0])),
+            Err(KeywordResponseError::WeightCount {
+                weights: 1,
+                responses: 2
+            })
+        );
+        assert_eq!(
+            kwr(&["hi"], &["a", "b"], Some(vec![1.0, -1.0])),
+            Err(KeywordResponseError::InvalidWeight(-1.0))
+        );
+        assert_eq!(
+            kwr(&["hi"], &["a", "b"], Some(vec![0.0, 0.0])),
+            Err(KeywordResponseError::AllWeightsZero)
+        );
+    }
+}
@@ -0,0 +1,516 @@
+use poise::serenity_prelude::{
+    ChannelId, Context, CreateMessage, Http, Member, Mentionable, Message, RoleId, User,
+};
+use problemo::*;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::E;
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+use tracing::{info, instrument, warn};
+
+use crate::custom_types::mongo_schema::ServerConfig;
+use crate::db::{Database, LevelUpMode};
+
+pub const DEFAULT_LEVEL_UP_MESSAGE: &str = "{user} leveled up from {old_level} to {new_level}!";
+
+// The original curve, tuned so that level 1 takes a handful of messages and every level after
+// takes about 4% more XP than the last
+const XP_MULTIPLIER: f64 = 15566f64;
+const XP_TRANSLATION: f64 = 15000f64;
+const EXPO_MULTIPLIER: f64 = 0.0415;
+
+// Defaults for guilds that haven't set their own reward ranges
+const DEFAULT_MONEY_RANGE: RangeInclusive<i64> = 5..=20;
+const DEFAULT_EXP_RANGE: RangeInclusive<i64> = 100..=100;
+
+static_gloss_error!(LevelRoleError, "Error while syncing level roles");
+static_gloss_error!(LevelUpMessageError, "Error while sending level up message");
+
+/// How much XP it takes to reach each level. Everyone starts at level 0 with 0 XP.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum XpCurve {
+    /// Each level takes a little more XP than the last
+    #[default]
+    Logarithmic,
+    /// Each level takes the same amount of XP
+    Linear { step: i64 },
+    /// Level n takes step * n^2 XP in total
+    Quadratic { step: i64 },
+    /// The total XP needed for each level, starting at level 1. Levels past the end of the table
+    /// each take as much XP as the last one in it did.
+    Table { levels: Vec<i64> },
+}
+
+impl XpCurve {
+    /// Reads a guild's curve, falling back to the original one if they haven't picked their own
+    pub fn from_conf(conf: Option<&ServerConfig>) -> serde_json::Result<XpCurve> {
+        conf.and_then(|c| c.xp_curve.as_deref())
+            .map_or(Ok(XpCurve::default()), serde_json::from_str)
+            .map(XpCurve::usable)
+    }
+
+    /// Falls back to the original curve if this one doesn't validate, so a bad curve saved in the
+    /// database can't stop anyone from leveling
+    pub fn usable(self) -> XpCurve {
+        match self.validate() {
+            Ok(()) => self,
+            Err(reason) => {
+                warn!("Ignoring invalid XP curve {self:?}, {reason}");
+                XpCurve::default()
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            XpCurve::Logarithmic => Ok(()),
+            XpCurve::Linear { step } | XpCurve::Quadratic { step } => {
+                if *step > 0 {
+                    Ok(())
+                } else {
+                    Err(String::from("the XP step has to be more than 0"))
+                }
+            }
+            XpCurve::Table { levels } => {
+                if levels.is_empty() {
+                    Err(String::from("the table needs at least one level"))
+                } else if levels[0] <= 0 {
+                    Err(String::from("level 1 has to take more than 0 XP"))
+                } else if levels.windows(2).any(|w| w[0] >= w[1]) {
+                    Err(String::from(
+                        "each level has to take more XP than the one before it",
+                    ))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// The level someone with this much XP is at
+    pub fn level(&self, exp: i64) -> i64 {
+        let exp = exp.max(0);
+
+        match self {
+            XpCurve::Logarithmic => {
+                let inside_log: f64 = (exp as f64 + XP_TRANSLATION) / XP_MULTIPLIER;
+
+                (((inside_log.log(E) / EXPO_MULTIPLIER) + 1f64) as i64).max(0)
+            }
+            XpCurve::Linear { step } => exp / step,
+            XpCurve::Quadratic { step } => {
+                // Float sqrt can be off by one for big numbers, so nudge it into place
+                let mut level = ((exp / step) as f64).sqrt() as i64;
+                while step * (level + 1).pow(2) <= exp {
+                    level += 1;
+                }
+                while level > 0 && step * level.pow(2) > exp {
+                    level -= 1;
+                }
+                level
+            }
+            XpCurve::Table { levels } => {
+                let reached = levels.partition_point(|l| *l <= exp) as i64;
+                let last = *levels.last().expect("tables are never empty");
+                if reached < levels.len() as i64 {
+                    return reached;
+                }
+
+                reached + (exp - last) / table_overflow_step(levels)
+            }
+        }
+    }
+
+    /// The total XP needed to reach a level
+    pub fn exp_for_level(&self, level: i64) -> i64 {
+        if level <= 0 {
+            return 0;
+        }
+
+        match self {
+            XpCurve::Logarithmic => {
+                let estimate = (XP_MULTIPLIER * (EXPO_MULTIPLIER * (level - 1) as f64).exp()
+                    - XP_TRANSLATION)
+                    .ceil()
+                    .max(0f64) as i64;
+
+                // Rounding can leave the estimate a little off, find the exact point
+                let mut exp = estimate;
+                while self.level(exp) < level {
+                    exp += 1;
+                }
+                while exp > 0 && self.level(exp - 1) >= level {
+                    exp -= 1;
+                }
+                exp
+            }
+            XpCurve::Linear { step } => step * level,
+            XpCurve::Quadratic { step } => step * level.pow(2),
+            XpCurve::Table { levels } => match levels.get(level as usize - 1) {
+                Some(exp) => *exp,
+                None => {
+                    let last = *levels.last().expect("tables are never empty");
+                    let extra_levels = level - levels.len() as i64;
+                    last + extra_levels * table_overflow_step(levels)
+                }
+            },
+        }
+    }
+
+    /// Where someone with this much XP is between their level and the next one
+    pub fn progress(&self, exp: i64) -> LevelProgress {
+        let level = self.level(exp);
+        let level_start = self.exp_for_level(level);
+        let next_level = self.exp_for_level(level + 1);
+
+        LevelProgress {
+            level,
+            exp_into_level: exp - level_start,
+            exp_for_next: next_level - level_start,
+        }
+    }
+}
+
+impl Display for XpCurve {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            XpCurve::Logarithmic => write!(f, "Logarithmic"),
+            XpCurve::Linear { step } => write!(f, "Linear ({step} XP per level)"),
+            XpCurve::Quadratic { step } => write!(f, "Quadratic ({step} XP * level²)"),
+            XpCurve::Table { levels } => write!(f, "Table ({} levels)", levels.len()),
+        }
+    }
+}
+
+// How much XP each level past the end of a table takes
+fn table_overflow_step(levels: &[i64]) -> i64 {
+    match levels {
+        [.., before_last, last] => last - before_last,
+        [only] => *only,
+        [] => unreachable!("tables are never empty"),
+    }
+}
+
+/// How far along someone is in their current level
+#[derive(Debug, Clone, Copy)]
+pub struct LevelProgress {
+    pub level: i64,
+    pub exp_into_level: i64,
+    /// The XP between this level and the next one
+    pub exp_for_next: i64,
+}
+
+/// How many V-Bucks and how much XP a rewarded message is worth
+#[derive(Debug, Clone)]
+pub struct RewardRanges {
+    pub money: RangeInclusive<i64>,
+    pub exp: RangeInclusive<i64>,
+}
+
+impl RewardRanges {
+    pub fn from_conf(conf: Option<&ServerConfig>) -> RewardRanges {
+        let range = |min: Option<i64>, max: Option<i64>, default: RangeInclusive<i64>| {
+            let min = min.unwrap_or(*default.start());
+            let max = max.unwrap_or(*default.end()).max(min);
+            min..=max
+        };
+
+        RewardRanges {
+            money: range(
+                conf.and_then(|c| c.money_min),
+                conf.and_then(|c| c.money_max),
+                DEFAULT_MONEY_RANGE,
+            ),
+            exp: range(
+                conf.and_then(|c| c.exp_min),
+                conf.and_then(|c| c.exp_max),
+                DEFAULT_EXP_RANGE,
+            ),
+        }
+    }
+}
+
+/// The level roles that were given to and taken from a member
+#[derive(Debug, Default)]
+pub struct RoleChanges {
+    pub added: Vec<RoleId>,
+    pub removed: Vec<RoleId>,
+}
+
+/// Splits a guild's level roles into the ones a member at this level should have and the ones
+/// they shouldn't. When replacing, only the roles for the highest level reached are kept.
+fn level_role_targets(
+    level_roles: &[(i64, RoleId)],
+    level: i64,
+    replace: bool,
+) -> (Vec<RoleId>, Vec<RoleId>) {
+    let earned = level_roles.iter().filter(|(l, _)| *l <= level);
+    let top_level = earned.clone().map(|(l, _)| *l).max();
+
+    let keep: Vec<RoleId> = earned
+        .filter(|(l, _)| !replace || Some(*l) == top_level)
+        .map(|(_, r)| *r)
+        .collect();
+    let drop = level_roles
+        .iter()
+        .map(|(_, r)| *r)
+        .filter(|r| !keep.contains(r))
+        .collect();
+
+    (keep, drop)
+}
+
+/// Gives a member every level role they have earned and takes away the ones they shouldn't have.
+/// Level roles are managed entirely by Johnson, so a level role given out by hand to someone
+/// that hasn't earned it will be taken away.
+#[instrument(skip(http, db, member), fields(member = member.user.name))]
+pub async fn sync_level_roles(
+    http: &Http,
+    db: &Database<'_>,
+    member: &Member,
+    level: i64,
+) -> Result<RoleChanges, Problem> {
+    let level_roles = db.get_level_roles(member.guild_id).await?;
+    if level_roles.is_empty() {
+        return Ok(RoleChanges::default());
+    }
+
+    let replace = match db.get_server_conf(member.guild_id).await {
+        Ok(conf) => conf.replace_level_roles,
+        Err(sqlx::Error::RowNotFound) => false,
+        Err(err) => return Err(err.into()),
+    };
+
+    let (keep, drop) = level_role_targets(&level_roles, level, replace);
+    let changes = RoleChanges {
+        added: keep
+            .into_iter()
+            .filter(|r| !member.roles.contains(r))
+            .collect(),
+        removed: drop
+            .into_iter()
+            .filter(|r| member.roles.contains(r))
+            .collect(),
+    };
+
+    if !changes.added.is_empty() {
+        member
+            .add_roles(http, &changes.added)
+            .await
+            .via(LevelRoleError::new(
+                "Couldn't give member their level roles",
+            ))?;
+    }
+
+    if !changes.removed.is_empty() {
+        member
+            .remove_roles(http, &changes.removed)
+            .await
+            .via(LevelRoleError::new(
+                "Couldn't take old level roles from member",
+            ))?;
+    }
+
+    info!(changes = ?changes, "Synced level roles");
+
+    Ok(changes)
+}
+
+/// Fills in the placeholders of a level up message template
+pub fn render_level_up_message(
+    template: &str,
+    user: &User,
+    old_level: i64,
+    new_level: i64,
+    vbucks: i64,
+) -> String {
+    template
+        .replace("{user}", &user.mention().to_string())
+        .replace("{old_level}", &old_level.to_string())
+        .replace("{new_level}", &new_level.to_string())
+        .replace("{vbucks}", &vbucks.to_string())
+}
+
+/// Lets a member know they leveled up, wherever their guild wants level up messages to go
+#[instrument(skip(ctx, conf, message))]
+pub async fn announce_level_up(
+    ctx: &Context,
+    conf: Option<&ServerConfig>,
+    message: &Message,
+    old_level: i64,
+    new_level: i64,
+    vbucks: i64,
+) -> Result<(), Problem> {
+    let mode = conf.map_or(LevelUpMode::Reply, |c| {
+        LevelUpMode::from_u8(c.level_up_mode as u8)
+    });
+    let template = conf
+        .and_then(|c| c.level_up_message.as_deref())
+        .unwrap_or(DEFAULT_LEVEL_UP_MESSAGE);
+    let content = render_level_up_message(template, &message.author, old_level, new_level, vbucks);
+
+    // Fall back to replying if the channel was never set
+    let channel = conf
+        .and_then(|c| c.level_up_channel_id)
+        .map(|c| ChannelId::new(c as u64));
+
+    match (mode, channel) {
+        (LevelUpMode::Silent, _) => {}
+        (LevelUpMode::Direct, _) => {
+            message
+                .author
+                .direct_message(ctx, CreateMessage::new().content(content))
+                .await
+                .via(LevelUpMessageError::new("Couldn't DM member"))?;
+        }
+        (LevelUpMode::Channel, Some(channel)) => {
+            channel
+                .send_message(ctx, CreateMessage::new().content(content))
+                .await
+                .via(LevelUpMessageError::new(
+                    "Couldn't send to level up channel",
+                ))?;
+        }
+        (LevelUpMode::Reply, _) | (LevelUpMode::Channel, None) => {
+            message
+                .reply(ctx, content)
+                .await
+                .via(LevelUpMessageError::new("Couldn't reply to message"))?;
+        }
+    }
+
+    info!(mode = ?mode, "Announced level up");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_curves_fall_back_to_the_original() {
+        for curve in [
+            XpCurve::Linear { step: 0 },
+            XpCurve::Quadratic { step: -5 },
+            XpCurve::Table { levels: Vec::new() },
+            XpCurve::Table {
+                levels: vec![100, 50],
+            },
+        ] {
+            assert_eq!(curve.usable(), XpCurve::Logarithmic);
+        }
+
+        let curve = XpCurve::Linear { step: 10 };
+        assert_eq!(curve.clone().usable(), curve);
+    }
+
+    fn assert_round_trips(curve: &XpCurve, levels: std::ops::RangeInclusive<i64>) {
+        for level in levels {
+            let exp = curve.exp_for_level(level);
+            assert_eq!(curve.level(exp), level, "{curve} at {exp} XP");
+            assert_eq!(curve.level(exp - 1), level - 1, "{curve} at {} XP", exp - 1);
+        }
+    }
+
+    #[test]
+    fn logarithmic_round_trips() {
+        assert_round_trips(&XpCurve::Logarithmic, 1..=200);
+    }
+
+    #[test]
+    fn linear_round_trips() {
+        assert_round_trips(&XpCurve::Linear { step: 250 }, 1..=200);
+    }
+
+    #[test]
+    fn quadratic_round_trips() {
+        assert_round_trips(&XpCurve::Quadratic { step: 7 }, 1..=200);
+    }
+
+    #[test]
+    fn table_round_trips_past_its_end() {
+        let curve = XpCurve::Table {
+            levels: vec![100, 250, 500],
+        };
+
+        assert_round_trips(&curve, 1..=20);
+        // Every level past the table takes as long as its last level did
+        assert_eq!(curve.exp_for_level(4), 750);
+        assert_eq!(curve.level(1000), 5);
+    }
+
+    #[test]
+    fn single_level_table_repeats_it() {
+        let curve = XpCurve::Table { levels: vec![100] };
+
+        assert_round_trips(&curve, 1..=20);
+        assert_eq!(curve.exp_for_level(3), 300);
+    }
+
+    #[test]
+    fn everyone_starts_at_level_0() {
+        for curve in [
+            XpCurve::Logarithmic,
+            XpCurve::Linear { step: 10 },
+            XpCurve::Quadratic { step: 10 },
+            XpCurve::Table { levels: vec![10] },
+        ] {
+            assert_eq!(curve.level(0), 0);
+            assert_eq!(curve.exp_for_level(0), 0);
+        }
+    }
+
+    fn roles(ids: &[u64]) -> Vec<RoleId> {
+        ids.iter().map(|id| RoleId::new(*id)).collect()
+    }
+
+    const LEVEL_ROLES: [(i64, RoleId); 3] = [
+        (5, RoleId::new(1)),
+        (10, RoleId::new(2)),
+        (20, RoleId::new(3)),
+    ];
+
+    #[test]
+    fn leveling_up_earns_every_role_reached() {
+        assert_eq!(
+            level_role_targets(&LEVEL_ROLES, 12, false),
+            (roles(&[1, 2]), roles(&[3]))
+        );
+    }
+
+    #[test]
+    fn replacing_keeps_only_the_highest_role() {
+        assert_eq!(
+            level_role_targets(&LEVEL_ROLES, 12, true),
+            (roles(&[2]), roles(&[1, 3]))
+        );
+    }
+
+    #[test]
+    fn dropping_below_a_level_takes_its_role() {
+        assert_eq!(
+            level_role_targets(&LEVEL_ROLES, 9, false),
+            (roles(&[1]), roles(&[2, 3]))
+        );
+        assert_eq!(
+            level_role_targets(&LEVEL_ROLES, 0, true),
+            (roles(&[]), roles(&[1, 2, 3]))
+        );
+    }
+
+    #[test]
+    fn shared_levels_give_every_role_at_that_level() {
+        let level_roles = [
+            (5, RoleId::new(1)),
+            (10, RoleId::new(2)),
+            (10, RoleId::new(3)),
+        ];
+
+        assert_eq!(
+            level_role_targets(&level_roles, 10, true),
+            (roles(&[2, 3]), roles(&[1]))
+        );
+    }
+}
@@ -1,8 +1,18 @@
+// problemo's gloss_error macros derive Hash alongside a hand written PartialEq
+#![allow(clippy::derived_hash_with_manual_eq)]
+
+mod blackjack;
 mod checks;
+mod claims;
 mod commands;
 mod custom_types;
 mod events;
+mod keywords;
+mod levels;
 mod logging;
+mod rank_card;
+mod rng;
+mod throttle;
 // mod spotify;
 mod db;
 mod utils;
@@ -17,9 +27,12 @@ use sqlx::SqlitePool;
 use sqlx::sqlite::SqliteConnectOptions;
 use tracing::{error, info};
 
-use custom_types::command::{Data, Error, KeywordResponse, PartialData, SerenityCtxData};
+use custom_types::command::{Data, Error, PartialData, SerenityCtxData};
 
 use crate::custom_types::command::FriendInfo;
+use crate::keywords::KeywordCache;
+use crate::rng::RngProvider;
+use crate::throttle::ResponseThrottle;
 
 mod built_info {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
@@ -37,14 +50,19 @@ impl CommandRegistering {
         ctx: &serenity::Context,
         commands: &[Command<Data, Error>],
         db_conn: SqlitePool,
-        kwr: Vec<KeywordResponse>,
-        http: reqwest::Client,
+        keywords: KeywordCache,
+        rng: RngProvider,
     ) -> Result<Data, Problem> {
         match self {
             // Register the commands globally
             CommandRegistering::Global => {
                 poise::builtins::register_globally(ctx, commands).await?;
-                Ok(Data { db_conn, kwr, http })
+                Ok(Data {
+                    db_conn,
+                    keywords,
+                    throttle: ResponseThrottle::default(),
+                    rng,
+                })
             }
             // Register commands for every provided guild
             CommandRegistering::ByGuild(guilds) => {
@@ -52,7 +70,12 @@ impl CommandRegistering {
                     // Deref and copy guild_id
                     poise::builtins::register_in_guild(ctx, commands, *guild).await?;
                 }
-                Ok(Data { db_conn, kwr, http })
+                Ok(Data {
+                    db_conn,
+                    keywords,
+                    throttle: ResponseThrottle::default(),
+                    rng,
+                })
             }
         }
     }
@@ -118,6 +141,12 @@ async fn main() {
         error!("failed to migrate database: {}", err);
     }
 
+    match db::refund_orphaned_escrows(&pool).await {
+        Ok(0) => {}
+        Ok(refunded) => info!("Refunded {refunded} games that never finished"),
+        Err(err) => error!("failed to refund unfinished games: {}", err),
+    }
+
     // let music_commands = vec![
     //     commands::music::play(),
     //     commands::music::pause(),
@@ -133,11 +162,27 @@ async fn main() {
         commands::basic::version(),
         commands::basic::smile(),
         commands::basic::test_problem(),
+        commands::config::config(),
         commands::config::set_error_channel(),
         commands::config::view_server_conf(),
+        commands::economy::daily(),
+        commands::economy::weekly(),
+        commands::economy::economy(),
+        commands::economy::history(),
+        commands::economy::history_of(),
+        commands::economy::pay(),
+        commands::economy::gift(),
+        commands::gamble::blackjack(),
+        commands::gamble::coinflip(),
+        commands::gamble::dice(),
         commands::gamble::rock_paper_scissors(),
+        commands::gamble::rps_tournament(),
+        commands::gamble::slots(),
+        commands::keywords::keyword(),
+        commands::level_roles::level_role(),
         commands::roles::set_welcome_role(),
         commands::stats::show_stats(),
+        commands::stats::leaderboard(),
         commands::relations::add_friend(),
         commands::relations::block_user(),
         commands::relations::get_relationships(),
@@ -167,13 +212,21 @@ async fn main() {
     //     Ok(s) => s,
     // };
 
-    // KWR Config File
+    // KWR Config File, used as the starting keywords for new guilds
 
     let kwr_str = include_str!("../cfg/kwr.json");
-    let kw_responses: Vec<KeywordResponse> =
-        serde_json::from_str(kwr_str).expect("embedded kwr.json str should be valid json");
+    let kw_responses = match keywords::parse_keyword_responses(kwr_str) {
+        Ok(kw_responses) => kw_responses,
+        Err(invalid) => {
+            for kwr in invalid {
+                error!("invalid kwr.json entry, {kwr}");
+            }
+            return;
+        }
+    };
 
-    info!(kw_responses = ?kw_responses, "Loaded keywords");
+    info!(kw_responses = ?kw_responses, "Loaded seed keywords");
+    let keyword_cache = KeywordCache::new(kw_responses);
 
     let guilds = match std::env::var("LEVEL")
         .unwrap_or(String::from("DEBUG"))
@@ -193,11 +246,11 @@ async fn main() {
     // Set register type
     let registering = CommandRegistering::ByGuild(guilds);
 
-    let http_client = reqwest::Client::new();
+    // Shared between commands and events
+    let rng = RngProvider::from_entropy();
     let serenity_data = PartialData {
         db_conn: pool.clone(),
-        kwr: kw_responses.clone(),
-        welcome_role: None,
+        rng: rng.clone(),
         friend_info: FriendInfo {
             status: serenity::OnlineStatus::Offline,
             voice_status: None,
@@ -212,13 +265,7 @@ async fn main() {
             // Requires a pin that holds a future
             Box::pin(async move {
                 registering
-                    .register(
-                        ctx,
-                        &framework.options().commands,
-                        pool,
-                        kw_responses,
-                        http_client,
-                    )
+                    .register(ctx, &framework.options().commands, pool, keyword_cache, rng)
                     .await
                     .map_err(|err| err.into_problem())
             })
@@ -0,0 +1,112 @@
+use std::io::Cursor;
+
+use ab_glyph::{FontRef, PxScale};
+use image::{ImageFormat, ImageResult, Rgba, RgbaImage};
+use imageproc::drawing::{draw_filled_rect_mut, draw_text_mut, text_size};
+use imageproc::rect::Rect;
+use once_cell::sync::Lazy;
+
+use crate::levels::LevelProgress;
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 200;
+const MARGIN: i32 = 30;
+
+const BAR_Y: i32 = 135;
+const BAR_HEIGHT: u32 = 30;
+
+const BACKGROUND: Rgba<u8> = Rgba([35, 39, 42, 255]);
+const BAR_BACKGROUND: Rgba<u8> = Rgba([72, 75, 81, 255]);
+const ACCENT: Rgba<u8> = Rgba([88, 101, 242, 255]);
+const TEXT: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const SUBTEXT: Rgba<u8> = Rgba([185, 187, 190, 255]);
+
+static FONT: Lazy<FontRef<'static>> = Lazy::new(|| {
+    FontRef::try_from_slice(include_bytes!("../assets/DejaVuSans-Bold.ttf"))
+        .expect("bundled font should be valid")
+});
+
+/// Everything shown on a rank card
+#[derive(Debug)]
+pub struct RankCard<'a> {
+    pub name: &'a str,
+    pub progress: LevelProgress,
+    pub rank: Option<i64>,
+    pub member_count: i64,
+}
+
+impl RankCard<'_> {
+    /// Draws the card and encodes it as a PNG
+    pub fn render(&self) -> ImageResult<Vec<u8>> {
+        let mut card = RgbaImage::from_pixel(WIDTH, HEIGHT, BACKGROUND);
+        let font = &*FONT;
+
+        draw_text_mut(
+            &mut card,
+            TEXT,
+            MARGIN,
+            MARGIN,
+            PxScale::from(40.0),
+            font,
+            self.name,
+        );
+
+        let rank = self.rank.map_or(String::from("Unranked"), |r| {
+            format!("#{r} / {}", self.member_count)
+        });
+        draw_right_aligned(&mut card, SUBTEXT, MARGIN + 5, 30.0, &rank);
+
+        draw_text_mut(
+            &mut card,
+            ACCENT,
+            MARGIN,
+            90,
+            PxScale::from(30.0),
+            font,
+            &format!("Level {}", self.progress.level),
+        );
+
+        let exp = format!(
+            "{} / {} XP",
+            self.progress.exp_into_level, self.progress.exp_for_next
+        );
+        draw_right_aligned(&mut card, SUBTEXT, 95, 24.0, &exp);
+
+        let bar_width = WIDTH - 2 * MARGIN as u32;
+        draw_filled_rect_mut(
+            &mut card,
+            Rect::at(MARGIN, BAR_Y).of_size(bar_width, BAR_HEIGHT),
+            BAR_BACKGROUND,
+        );
+
+        let filled = (bar_width as f64 * progress_fraction(&self.progress)) as u32;
+        if filled > 0 {
+            draw_filled_rect_mut(
+                &mut card,
+                Rect::at(MARGIN, BAR_Y).of_size(filled, BAR_HEIGHT),
+                ACCENT,
+            );
+        }
+
+        let mut png = Vec::new();
+        card.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+
+        Ok(png)
+    }
+}
+
+/// How much of the way to the next level someone is, from 0 to 1
+pub fn progress_fraction(progress: &LevelProgress) -> f64 {
+    if progress.exp_for_next <= 0 {
+        return 1.0;
+    }
+
+    (progress.exp_into_level as f64 / progress.exp_for_next as f64).clamp(0.0, 1.0)
+}
+
+fn draw_right_aligned(card: &mut RgbaImage, color: Rgba<u8>, y: i32, scale: f32, text: &str) {
+    let (width, _) = text_size(PxScale::from(scale), &*FONT, text);
+    let x = WIDTH as i32 - MARGIN - width as i32;
+
+    draw_text_mut(card, color, x, y, PxScale::from(scale), &*FONT, text);
+}
@@ -0,0 +1,71 @@
+use std::sync::{Arc, Mutex};
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+/// Where all of Johnson's random choices come from. The bot seeds it from entropy, tests seed it
+/// with a fixed value so every roll comes out the same each run. Clones share one generator.
+#[derive(Debug, Clone)]
+pub struct RngProvider {
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl RngProvider {
+    pub fn from_entropy() -> RngProvider {
+        RngProvider::new(StdRng::from_os_rng())
+    }
+
+    #[cfg(test)]
+    pub fn seeded(seed: u64) -> RngProvider {
+        RngProvider::new(StdRng::seed_from_u64(seed))
+    }
+
+    fn new(rng: StdRng) -> RngProvider {
+        RngProvider {
+            rng: Arc::new(Mutex::new(rng)),
+        }
+    }
+
+    /// Runs f with the generator locked, so don't await anything inside it
+    pub fn with<T>(&self, f: impl FnOnce(&mut StdRng) -> T) -> T {
+        let mut rng = self.rng.lock().expect("rng lock should not be poisoned");
+        f(&mut rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn same_seed_same_numbers() {
+        let first = RngProvider::seeded(11);
+        let second = RngProvider::seeded(11);
+
+        for _ in 0..10 {
+            assert_eq!(
+                first.with(|rng| rng.random::<u64>()),
+                second.with(|rng| rng.random::<u64>())
+            );
+        }
+    }
+
+    #[test]
+    fn clones_share_a_generator() {
+        let provider = RngProvider::seeded(11);
+        let clone = provider.clone();
+        let reference = RngProvider::seeded(11);
+
+        let drawn = [
+            provider.with(|rng| rng.random::<u64>()),
+            clone.with(|rng| rng.random::<u64>()),
+        ];
+        let expected = [
+            reference.with(|rng| rng.random::<u64>()),
+            reference.with(|rng| rng.random::<u64>()),
+        ];
+
+        assert_eq!(drawn, expected);
+    }
+}
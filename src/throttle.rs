@@ -0,0 +1,294 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use poise::serenity_prelude::{ChannelId, GuildId, UserId};
+
+use crate::custom_types::mongo_schema::ServerConfig;
+
+const DEFAULT_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+pub const MAX_LIMIT_WINDOW: Duration = Duration::from_secs(60 * 60);
+// Only bother cleaning out stale entries once there are a decent amount of them
+const PRUNE_THRESHOLD: usize = 256;
+
+/// What made Johnson respond to a message on his own
+#[derive(Hash, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ResponseSource {
+    /// A keyword response, by its id
+    Keyword(i64),
+    DadBot,
+}
+
+/// A guild's limits on how often Johnson can respond to messages on his own
+#[derive(Debug, Clone)]
+pub struct ResponseLimits {
+    /// Max responses in a channel per window
+    pub channel_limit: Option<usize>,
+    /// Max responses to a single user per window
+    pub user_limit: Option<usize>,
+    pub window: Duration,
+    pub dad_bot_cooldown: Option<Duration>,
+}
+
+impl Default for ResponseLimits {
+    fn default() -> Self {
+        ResponseLimits {
+            channel_limit: None,
+            user_limit: None,
+            window: DEFAULT_LIMIT_WINDOW,
+            dad_bot_cooldown: None,
+        }
+    }
+}
+
+impl From<&ServerConfig> for ResponseLimits {
+    fn from(conf: &ServerConfig) -> Self {
+        ResponseLimits {
+            channel_limit: conf.kw_channel_limit.map(|l| l as usize),
+            user_limit: conf.kw_user_limit.map(|l| l as usize),
+            window: conf
+                .kw_limit_window
+                .map_or(DEFAULT_LIMIT_WINDOW, |w| Duration::from_secs(w as u64))
+                .min(MAX_LIMIT_WINDOW),
+            dad_bot_cooldown: conf.dad_bot_cooldown.map(|c| Duration::from_secs(c as u64)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ThrottleState {
+    // When each source can respond again in a channel
+    cooldowns: HashMap<(GuildId, ChannelId, ResponseSource), Instant>,
+    // When the most recent responses in each channel and to each user were sent
+    channel_responses: HashMap<(GuildId, ChannelId), VecDeque<Instant>>,
+    user_responses: HashMap<(GuildId, UserId), VecDeque<Instant>>,
+}
+
+impl ThrottleState {
+    fn prune(&mut self, now: Instant) {
+        if self.cooldowns.len() > PRUNE_THRESHOLD {
+            self.cooldowns.retain(|_, ready_at| *ready_at > now);
+        }
+
+        // No window is longer than MAX_LIMIT_WINDOW, so anything older than that is useless
+        if self.channel_responses.len() > PRUNE_THRESHOLD {
+            self.channel_responses
+                .retain(|_, sent| sent.back().is_some_and(|t| now - *t < MAX_LIMIT_WINDOW));
+        }
+
+        if self.user_responses.len() > PRUNE_THRESHOLD {
+            self.user_responses
+                .retain(|_, sent| sent.back().is_some_and(|t| now - *t < MAX_LIMIT_WINDOW));
+        }
+    }
+}
+
+/// In-memory record of Johnson's automatic responses, used to stop him from flooding channels.
+/// Nothing here survives a restart, which is fine since every limit is short lived.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseThrottle {
+    state: Arc<Mutex<ThrottleState>>,
+}
+
+impl ResponseThrottle {
+    /// Checks a response against its cooldown and the guild's limits, recording it if it's
+    /// allowed. Returns false if Johnson should stay quiet.
+    pub fn try_respond(
+        &self,
+        guild: GuildId,
+        channel: ChannelId,
+        user: UserId,
+        source: ResponseSource,
+        cooldown: Option<Duration>,
+        limits: &ResponseLimits,
+    ) -> bool {
+        self.try_respond_at(
+            Instant::now(),
+            guild,
+            channel,
+            user,
+            source,
+            cooldown,
+            limits,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn try_respond_at(
+        &self,
+        now: Instant,
+        guild: GuildId,
+        channel: ChannelId,
+        user: UserId,
+        source: ResponseSource,
+        cooldown: Option<Duration>,
+        limits: &ResponseLimits,
+    ) -> bool {
+        let mut state = self
+            .state
+            .lock()
+            .expect("throttle lock should not be poisoned");
+        state.prune(now);
+
+        if state
+            .cooldowns
+            .get(&(guild, channel, source))
+            .is_some_and(|ready_at| *ready_at > now)
+        {
+            return false;
+        }
+
+        let channel_sent = state.channel_responses.entry((guild, channel)).or_default();
+        drop_expired(channel_sent, now, limits.window);
+        if limits
+            .channel_limit
+            .is_some_and(|limit| channel_sent.len() >= limit)
+        {
+            return false;
+        }
+
+        let user_sent = state.user_responses.entry((guild, user)).or_default();
+        drop_expired(user_sent, now, limits.window);
+        if limits
+            .user_limit
+            .is_some_and(|limit| user_sent.len() >= limit)
+        {
+            return false;
+        }
+
+        user_sent.push_back(now);
+        state
+            .channel_responses
+            .entry((guild, channel))
+            .or_default()
+            .push_back(now);
+
+        if let Some(cooldown) = cooldown {
+            state
+                .cooldowns
+                .insert((guild, channel, source), now + cooldown);
+        }
+
+        true
+    }
+}
+
+fn drop_expired(sent: &mut VecDeque<Instant>, now: Instant, window: Duration) {
+    while sent.front().is_some_and(|t| now - *t >= window) {
+        sent.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId::new(1);
+    const CHANNEL: ChannelId = ChannelId::new(10);
+    const OTHER_CHANNEL: ChannelId = ChannelId::new(11);
+    const USER: UserId = UserId::new(100);
+    const OTHER_USER: UserId = UserId::new(101);
+    const KEYWORD: ResponseSource = ResponseSource::Keyword(1);
+
+    fn limits(channel_limit: Option<usize>, user_limit: Option<usize>) -> ResponseLimits {
+        ResponseLimits {
+            channel_limit,
+            user_limit,
+            window: Duration::from_secs(60),
+            dad_bot_cooldown: None,
+        }
+    }
+
+    #[test]
+    fn channel_limit_lifts_exactly_at_the_window_end() {
+        let throttle = ResponseThrottle::default();
+        let limits = limits(Some(2), None);
+        let start = Instant::now();
+        let respond = |at: Duration, user| {
+            throttle.try_respond_at(start + at, GUILD, CHANNEL, user, KEYWORD, None, &limits)
+        };
+
+        assert!(respond(Duration::ZERO, USER));
+        assert!(respond(Duration::from_secs(1), OTHER_USER));
+        assert!(!respond(Duration::from_secs(2), USER));
+        assert!(!respond(Duration::from_millis(59_999), USER));
+        // The first response is a full window old now
+        assert!(respond(Duration::from_secs(60), USER));
+        assert!(!respond(Duration::from_millis(60_500), USER));
+    }
+
+    #[test]
+    fn user_limit_follows_them_across_channels() {
+        let throttle = ResponseThrottle::default();
+        let limits = limits(None, Some(1));
+        let now = Instant::now();
+
+        assert!(throttle.try_respond_at(now, GUILD, CHANNEL, USER, KEYWORD, None, &limits));
+        assert!(!throttle.try_respond_at(now, GUILD, OTHER_CHANNEL, USER, KEYWORD, None, &limits));
+        assert!(throttle.try_respond_at(now, GUILD, CHANNEL, OTHER_USER, KEYWORD, None, &limits));
+        // Limits are kept per guild
+        assert!(throttle.try_respond_at(
+            now,
+            GuildId::new(2),
+            CHANNEL,
+            USER,
+            KEYWORD,
+            None,
+            &limits
+        ));
+    }
+
+    #[test]
+    fn channel_limit_is_per_channel() {
+        let throttle = ResponseThrottle::default();
+        let limits = limits(Some(1), None);
+        let now = Instant::now();
+
+        assert!(throttle.try_respond_at(now, GUILD, CHANNEL, USER, KEYWORD, None, &limits));
+        assert!(!throttle.try_respond_at(now, GUILD, CHANNEL, OTHER_USER, KEYWORD, None, &limits));
+        assert!(throttle.try_respond_at(now, GUILD, OTHER_CHANNEL, USER, KEYWORD, None, &limits));
+    }
+
+    #[test]
+    fn cooldown_expires() {
+        let throttle = ResponseThrottle::default();
+        let limits = ResponseLimits::default();
+        let cooldown = Some(Duration::from_secs(30));
+        let start = Instant::now();
+        let respond = |at: Duration, source| {
+            throttle.try_respond_at(start + at, GUILD, CHANNEL, USER, source, cooldown, &limits)
+        };
+
+        assert!(respond(Duration::ZERO, KEYWORD));
+        assert!(!respond(Duration::from_secs(29), KEYWORD));
+        // Other sources have their own cooldowns
+        assert!(respond(Duration::from_secs(29), ResponseSource::DadBot));
+        assert!(respond(Duration::from_secs(30), KEYWORD));
+    }
+
+    #[test]
+    fn refused_responses_dont_count() {
+        let throttle = ResponseThrottle::default();
+        let limits = limits(Some(1), None);
+        let start = Instant::now();
+        let respond = |at: Duration| {
+            throttle.try_respond_at(start + at, GUILD, CHANNEL, USER, KEYWORD, None, &limits)
+        };
+
+        assert!(respond(Duration::ZERO));
+        assert!(!respond(Duration::from_secs(30)));
+        // Only the first response is in the window, the refused one didn't start a new one
+        assert!(respond(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn no_limits_never_throttles() {
+        let throttle = ResponseThrottle::default();
+        let limits = ResponseLimits::default();
+        let now = Instant::now();
+
+        for _ in 0..100 {
+            assert!(throttle.try_respond_at(now, GUILD, CHANNEL, USER, KEYWORD, None, &limits));
+        }
+    }
+}
@@ -32,8 +32,8 @@ pub async fn simple_channel_message(ctx: &Context<'_>, content: &str) -> Result<
 pub mod interaction {
     use futures::StreamExt;
     use poise::serenity_prelude::{
-        ComponentInteraction, CreateInteractionResponse, CreateInteractionResponseMessage, Message,
-        UserId,
+        ComponentInteraction, CreateActionRow, CreateButton, CreateInteractionResponse,
+        CreateInteractionResponseMessage, Message, UserId,
     };
     use std::time::Duration;
 
@@ -71,6 +71,18 @@ pub mod interaction {
 
         None
     }
+
+    /// Previous and next buttons for paging through a list. Pages start at 0.
+    pub fn page_buttons(page: i64, page_count: i64) -> Vec<CreateActionRow> {
+        vec![CreateActionRow::Buttons(vec![
+            CreateButton::new("prev")
+                .label("Previous")
+                .disabled(page == 0),
+            CreateButton::new("next")
+                .label("Next")
+                .disabled(page >= page_count - 1),
+        ])]
+    }
 }
 
 pub mod embed {